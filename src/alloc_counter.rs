@@ -0,0 +1,102 @@
+//! This module counts heap allocations, so benchmarks can show whether a
+//! solver variant actually allocates less. Counting makes every allocation a
+//! bit slower, so the counting allocator is only used if the
+//! `count-allocations` feature is enabled:
+//!
+//! ```text
+//! cargo build --release --features count-allocations
+//! ```
+//!
+//! Allocations of all threads are counted.
+
+use crate::bench::Allocations;
+
+#[cfg(feature = "count-allocations")]
+mod counting {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    pub static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    pub static BYTES_ALLOCATED: AtomicU64 = AtomicU64::new(0);
+    pub static LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+    pub static PEAK_LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+    pub struct CountingAllocator;
+
+    fn allocated(size: usize) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES_ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+        let live = LIVE_BYTES.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+        PEAK_LIVE_BYTES.fetch_max(live, Ordering::Relaxed);
+    }
+    fn deallocated(size: usize) {
+        LIVE_BYTES.fetch_sub(size as u64, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let pointer = System.alloc(layout);
+            if !pointer.is_null() {
+                allocated(layout.size());
+            }
+            pointer
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let pointer = System.alloc_zeroed(layout);
+            if !pointer.is_null() {
+                allocated(layout.size());
+            }
+            pointer
+        }
+
+        unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+            System.dealloc(pointer, layout);
+            deallocated(layout.size());
+        }
+
+        // A reallocation counts as a new allocation of the new size.
+        unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_pointer = System.realloc(pointer, layout, new_size);
+            if !new_pointer.is_null() {
+                deallocated(layout.size());
+                allocated(new_size);
+            }
+            new_pointer
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+}
+
+/// Runs the function and returns how much it allocated, or `None` if
+/// allocations are not counted. The peak is measured relative to the heap
+/// that was already live before.
+#[cfg(feature = "count-allocations")]
+pub fn measure<T>(function: impl FnOnce() -> T) -> (T, Option<Allocations>) {
+    use counting::*;
+    use std::sync::atomic::Ordering;
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = BYTES_ALLOCATED.load(Ordering::Relaxed);
+    let live_before = LIVE_BYTES.load(Ordering::Relaxed);
+    PEAK_LIVE_BYTES.store(live_before, Ordering::Relaxed);
+
+    let result = function();
+
+    let allocations = Allocations {
+        count: ALLOCATIONS.load(Ordering::Relaxed) - allocations_before,
+        bytes: BYTES_ALLOCATED.load(Ordering::Relaxed) - bytes_before,
+        peak_live_bytes: PEAK_LIVE_BYTES
+            .load(Ordering::Relaxed)
+            .saturating_sub(live_before),
+    };
+    (result, Some(allocations))
+}
+#[cfg(not(feature = "count-allocations"))]
+pub fn measure<T>(function: impl FnOnce() -> T) -> (T, Option<Allocations>) {
+    (function(), None)
+}
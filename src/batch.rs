@@ -0,0 +1,229 @@
+//! Generating many Kakuros with unique solutions at once, for example for a
+//! collection of puzzles. Every candidate is generated from its own seed, so
+//! each Kakuro can be generated again on its own. Kakuros that are just
+//! rotations or reflections of others are dropped.
+
+use crate::{
+    generate::{self, Cell, Rejections},
+    layout,
+    rating::{self, Difficulty, Rating},
+    solvers::{CancellationToken, Monitor},
+    uniqueness,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Gives up after this many candidates per requested Kakuro.
+const MAX_CANDIDATES_PER_KAKURO: u64 = 100;
+
+pub struct Options {
+    pub layout: layout::Options,
+    /// Only keep Kakuros of this difficulty.
+    pub difficulty: Option<Difficulty>,
+    /// How long generating a single candidate may take.
+    pub timeout: Duration,
+    /// The seed of the first candidate. The others get the following seeds.
+    pub seed: u64,
+    pub count: usize,
+    pub threads: usize,
+}
+
+pub struct Kakuro {
+    pub grid: Vec<Vec<Cell>>,
+    /// The content hash of the Kakuro, which is the same for its rotations
+    /// and reflections.
+    pub hash: String,
+    pub seed: u64,
+    pub rating: Rating,
+    /// How many solutions the check for uniqueness found, which is always 1
+    /// for Kakuros that are kept.
+    pub solutions: usize,
+}
+
+/// An entry of the index file of a batch.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub file: String,
+    pub hash: String,
+    pub width: usize,
+    pub height: usize,
+    /// The fraction of cells that are empty.
+    pub density: f64,
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub guesses: u64,
+    pub solutions: usize,
+}
+
+impl Entry {
+    pub fn new(file: String, kakuro: &Kakuro) -> Self {
+        let cells = kakuro.grid.iter().flatten();
+        let digits = cells
+            .clone()
+            .filter(|cell| matches!(cell, Cell::Value(_)))
+            .count();
+        Self {
+            file,
+            hash: kakuro.hash.clone(),
+            width: kakuro.grid[0].len(),
+            height: kakuro.grid.len(),
+            density: digits as f64 / cells.count() as f64,
+            seed: kakuro.seed,
+            difficulty: kakuro.rating.difficulty,
+            guesses: kakuro.rating.guesses,
+            solutions: kakuro.solutions,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Statistics {
+    pub candidates: u64,
+    pub duplicates: usize,
+    /// Candidates that couldn't be generated in time or didn't have a unique
+    /// solution.
+    pub failures: usize,
+    /// Candidates of other difficulties, only counted when generating Kakuros
+    /// of a difficulty.
+    pub rejections: Rejections,
+}
+
+/// Generates Kakuros on several threads until there are enough different
+/// ones. Returns them ordered by seed. Except for candidates that time out,
+/// the result only depends on the options, not on the number of threads.
+pub fn generate(options: &Options) -> Result<(Vec<Kakuro>, Statistics), String> {
+    // Fail early if no layout with these options exists.
+    options
+        .layout
+        .generate(&mut StdRng::seed_from_u64(options.seed))?;
+    let next_seed = AtomicU64::new(0);
+    let distinct = AtomicUsize::new(0);
+    // For each content hash, the Kakuros with that hash.
+    let kakuros: Mutex<HashMap<String, Vec<Kakuro>>> = Mutex::new(HashMap::new());
+    let statistics = Mutex::new(Statistics::default());
+    let error: Mutex<Option<String>> = Mutex::new(None);
+    let max_candidates = options.count as u64 * MAX_CANDIDATES_PER_KAKURO;
+    thread::scope(|scope| {
+        for _ in 0..options.threads.max(1) {
+            let (next_seed, distinct, kakuros, statistics, error) =
+                (&next_seed, &distinct, &kakuros, &statistics, &error);
+            scope.spawn(move || loop {
+                if distinct.load(Ordering::Relaxed) >= options.count {
+                    break;
+                }
+                let index = next_seed.fetch_add(1, Ordering::Relaxed);
+                if index >= max_candidates {
+                    break;
+                }
+                let seed = options.seed.wrapping_add(index);
+                let mut rejections = Rejections::default();
+                let result = candidate(options, seed, &mut rejections);
+                let mut statistics = statistics.lock().unwrap();
+                statistics.candidates += 1;
+                statistics.rejections.not_unique += rejections.not_unique;
+                statistics.rejections.too_easy += rejections.too_easy;
+                statistics.rejections.too_hard += rejections.too_hard;
+                match result {
+                    Ok(Some(kakuro)) => {
+                        let mut kakuros = kakuros.lock().unwrap();
+                        let same = kakuros.entry(kakuro.hash.clone()).or_default();
+                        if same.is_empty() {
+                            distinct.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            statistics.duplicates += 1;
+                        }
+                        same.push(kakuro);
+                    }
+                    Ok(None) => statistics.failures += 1,
+                    Err(err) => {
+                        error.lock().unwrap().get_or_insert(err);
+                        distinct.store(options.count, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    if let Some(error) = error.into_inner().unwrap() {
+        return Err(error);
+    }
+    let statistics = statistics.into_inner().unwrap();
+    // Of Kakuros with the same hash, keep the one with the lowest
+    // seed, so that it doesn't matter which thread was faster.
+    let mut kakuros = kakuros
+        .into_inner()
+        .unwrap()
+        .into_values()
+        .map(|same| same.into_iter().min_by_key(|it| it.seed).unwrap())
+        .collect::<Vec<_>>();
+    kakuros.sort_by_key(|kakuro| kakuro.seed);
+    kakuros.truncate(options.count);
+    if kakuros.len() < options.count {
+        return Err(format!(
+            "Only found {} different Kakuros in {} candidates.",
+            kakuros.len(),
+            statistics.candidates
+        ));
+    }
+    Ok((kakuros, statistics))
+}
+
+/// Generates the candidate of the seed. Returns `None` if it couldn't be
+/// generated in time or doesn't have a unique solution.
+fn candidate(
+    options: &Options,
+    seed: u64,
+    rejections: &mut Rejections,
+) -> Result<Option<Kakuro>, String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (grid, rating) = match options.difficulty {
+        Some(difficulty) => match generate::generate_with_difficulty(
+            &options.layout,
+            difficulty,
+            options.timeout,
+            &mut rng,
+            rejections,
+        ) {
+            Ok((grid, rating)) => (grid, Some(rating)),
+            Err(_) => return Ok(None),
+        },
+        None => {
+            let layout = options.layout.generate(&mut rng)?;
+            let monitor = Monitor::new(CancellationToken::new(), Some(options.timeout));
+            match uniqueness::fill_uniquely(&layout, &mut rng, &monitor) {
+                Ok(grid) => (grid, None),
+                Err(_) => return Ok(None),
+            }
+        }
+    };
+    // Check the uniqueness independently of how the grid was filled.
+    let board = generate::to_board(&grid);
+    let input = board.to_input();
+    let monitor = Monitor::new(CancellationToken::new(), Some(options.timeout));
+    let solutions = match uniqueness::solutions(&input, 2, &monitor) {
+        Ok(solutions) if solutions.len() == 1 => solutions.len(),
+        _ => return Ok(None),
+    };
+    let rating = match rating {
+        Some(rating) => rating,
+        None => match rating::rate(&input, &monitor) {
+            Ok(Some(rating)) => rating,
+            _ => return Ok(None),
+        },
+    };
+    Ok(Some(Kakuro {
+        grid,
+        hash: board.content_hash(),
+        seed,
+        rating,
+        solutions,
+    }))
+}
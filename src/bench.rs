@@ -0,0 +1,670 @@
+//! This module contains the results of benchmarks. They can be stored as JSON
+//! or CSV and turned into the measurement table and raw measurement list of
+//! the README.
+
+use crate::stats::{self, Interval};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter, Write},
+    fs,
+    io::Read,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchResults {
+    pub machine: Machine,
+    /// The git revision of the measured code, with a `-dirty` suffix if there
+    /// were uncommitted changes.
+    pub revision: Option<String>,
+    pub date: String,
+    pub measurements: Vec<Measurement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Machine {
+    pub os: String,
+    pub arch: String,
+    pub cpu: Option<String>,
+    pub cores: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Measurement {
+    pub solver: String,
+    /// A short name of the input, like `small` for `kakuros/small.kakuro`.
+    pub input: String,
+    pub file: String,
+    pub outcome: Outcome,
+    /// The runtimes of all runs in nanoseconds.
+    pub samples: Vec<u64>,
+    pub summary: Option<Summary>,
+    /// What each run allocated, if the binary was built with the
+    /// `count-allocations` feature.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allocations: Vec<Allocations>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Allocations {
+    pub count: u64,
+    pub bytes: u64,
+    /// How much more heap was live at the peak than before the run.
+    pub peak_live_bytes: u64,
+}
+impl Display for Allocations {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations, {} allocated, {} peak",
+            self.count,
+            format_bytes(self.bytes as f64),
+            format_bytes(self.peak_live_bytes as f64)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outcome {
+    Solved,
+    Timeout,
+    OutOfMemory,
+    /// The solver found solutions that are invalid or not the expected ones.
+    Wrong {
+        message: String,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+/// Statistics about the samples of a measurement, all in nanoseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub median: f64,
+    pub mean: f64,
+    pub std_deviation: f64,
+    pub min: f64,
+    pub max: f64,
+    // Results stored before these existed don't contain them.
+    #[serde(default)]
+    pub percentile_5: f64,
+    #[serde(default)]
+    pub percentile_95: f64,
+    /// The median absolute deviation.
+    #[serde(default)]
+    pub mad: f64,
+    /// How many samples are outliers according to `stats::outliers`.
+    #[serde(default)]
+    pub outliers: usize,
+    /// The confidence interval of the median.
+    #[serde(default)]
+    pub median_interval: Interval,
+}
+
+pub const CONFIDENCE: f64 = 0.95;
+
+impl Summary {
+    pub fn of(samples: &[u64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let samples = samples.iter().map(|it| *it as f64).collect_vec();
+        let sorted = stats::sorted(&samples);
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        let variance = sorted
+            .iter()
+            .map(|value| (value - mean) * (value - mean))
+            .sum::<f64>()
+            / count as f64;
+        Some(Self {
+            median: stats::median(&sorted),
+            mean,
+            std_deviation: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[count - 1],
+            percentile_5: stats::percentile(&sorted, 0.05),
+            percentile_95: stats::percentile(&sorted, 0.95),
+            mad: stats::median_absolute_deviation(&sorted),
+            outliers: stats::outliers(&samples).len(),
+            median_interval: stats::bootstrap_median(&samples, CONFIDENCE),
+        })
+    }
+
+    /// The median with the standard deviation relative to it, as well as the
+    /// minimum and maximum, like `1.23 ms +- 0.64 %; 1.22 ms – 1.24 ms`. This is
+    /// the format of the README.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} +- {:.2} %; {} – {}",
+            format_duration(self.median),
+            self.std_deviation / self.median * 100.0,
+            format_duration(self.min),
+            format_duration(self.max)
+        )
+    }
+
+    /// The robust statistics, like
+    /// `MAD 0.31 %, 5–95 % 1.22 ms – 1.24 ms, median CI 1.22 ms – 1.23 ms, 1 outliers`.
+    pub fn describe_details(&self) -> String {
+        format!(
+            "MAD {:.2} %, 5–95 % {} – {}, median CI {} – {}, {} outliers",
+            self.mad / self.median * 100.0,
+            format_duration(self.percentile_5),
+            format_duration(self.percentile_95),
+            format_duration(self.median_interval.low),
+            format_duration(self.median_interval.high),
+            self.outliers
+        )
+    }
+}
+
+impl Machine {
+    pub fn current() -> Self {
+        // Only Linux tells us the CPU model this easily.
+        let cpu = fs::read_to_string("/proc/cpuinfo").ok().and_then(|info| {
+            info.lines()
+                .find(|line| line.starts_with("model name"))
+                .and_then(|line| line.split_once(':'))
+                .map(|(_, model)| model.trim().to_string())
+        });
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpu,
+            cores: std::thread::available_parallelism()
+                .map(|it| it.get())
+                .unwrap_or(1),
+        }
+    }
+}
+
+fn git_revision() -> Option<String> {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let revision = git(&["rev-parse", "--short", "HEAD"])?;
+    let is_dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    Some(if is_dirty {
+        format!("{}-dirty", revision)
+    } else {
+        revision
+    })
+}
+
+impl Outcome {
+    /// How the outcome is shown in the README.
+    fn short(&self) -> &str {
+        match self {
+            Outcome::Solved => "solved",
+            Outcome::Timeout => "timeout",
+            Outcome::OutOfMemory => "oom",
+            Outcome::Wrong { .. } => "wrong",
+            Outcome::Failed { .. } => "failed",
+        }
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Wrong { message } => write!(f, "wrong: {}", message),
+            Outcome::Failed { message } => write!(f, "failed: {}", message),
+            outcome => outcome.short().fmt(f),
+        }
+    }
+}
+
+impl Measurement {
+    /// The medians of the allocation statistics of all runs.
+    pub fn median_allocations(&self) -> Option<Allocations> {
+        if self.allocations.is_empty() {
+            return None;
+        }
+        let median = |value: fn(&Allocations) -> u64| {
+            let values = self
+                .allocations
+                .iter()
+                .map(|it| value(it) as f64)
+                .collect_vec();
+            stats::median(&stats::sorted(&values)) as u64
+        };
+        Some(Allocations {
+            count: median(|it| it.count),
+            bytes: median(|it| it.bytes),
+            peak_live_bytes: median(|it| it.peak_live_bytes),
+        })
+    }
+
+    fn table_cell(&self) -> String {
+        match (&self.outcome, &self.summary) {
+            (Outcome::Solved, Some(summary)) => format_duration(summary.median),
+            (outcome, _) => outcome.short().to_string(),
+        }
+    }
+
+    fn raw(&self) -> String {
+        match (&self.outcome, &self.summary) {
+            (Outcome::Solved, Some(summary)) => summary.describe(),
+            (outcome, _) => outcome.short().to_string(),
+        }
+    }
+}
+
+impl BenchResults {
+    /// Results measured right now on this machine.
+    pub fn new(measurements: Vec<Measurement>) -> Self {
+        Self {
+            machine: Machine::current(),
+            revision: git_revision(),
+            date: chrono::Local::now().to_rfc3339(),
+            measurements,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|err| format!("Invalid benchmark results: {}", err))
+    }
+
+    /// One line per measurement. The samples are separated by spaces.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        writeln!(
+            csv,
+            "solver,input,outcome,median,mean,std_deviation,min,max,samples,revision"
+        )
+        .unwrap();
+        for measurement in &self.measurements {
+            let summary = match &measurement.summary {
+                Some(summary) => format!(
+                    "{},{},{},{},{}",
+                    summary.median, summary.mean, summary.std_deviation, summary.min, summary.max
+                ),
+                None => ",,,,".to_string(),
+            };
+            writeln!(
+                csv,
+                "{},{},{},{},{},{}",
+                measurement.solver,
+                measurement.input,
+                measurement.outcome.short(),
+                summary,
+                measurement.samples.iter().join(" "),
+                self.revision.as_deref().unwrap_or("")
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+/// The newest measurement of each solver on each input. Later results override
+/// earlier ones. Solvers and inputs are ordered by when they first appear.
+struct Report<'a> {
+    solvers: Vec<&'a str>,
+    inputs: Vec<&'a str>,
+    measurements: Vec<&'a Measurement>,
+}
+impl<'a> Report<'a> {
+    fn new(results: &'a [BenchResults]) -> Self {
+        let all = results
+            .iter()
+            .flat_map(|results| &results.measurements)
+            .collect_vec();
+        Self {
+            solvers: all.iter().map(|it| it.solver.as_str()).unique().collect(),
+            inputs: all.iter().map(|it| it.input.as_str()).unique().collect(),
+            measurements: all,
+        }
+    }
+
+    fn get(&self, solver: &str, input: &str) -> Option<&'a Measurement> {
+        self.measurements
+            .iter()
+            .rev()
+            .find(|it| it.solver == solver && it.input == input)
+            .copied()
+    }
+
+    fn table(&self) -> String {
+        let solver_width = self
+            .solvers
+            .iter()
+            .map(|it| it.len())
+            .chain(["solver".len()])
+            .max()
+            .unwrap();
+        let cells = self
+            .solvers
+            .iter()
+            .map(|solver| {
+                self.inputs
+                    .iter()
+                    .map(|input| match self.get(solver, input) {
+                        Some(measurement) => measurement.table_cell(),
+                        None => "todo".to_string(),
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        let input_widths = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([input.len()])
+                    .max()
+                    .unwrap()
+            })
+            .collect_vec();
+
+        let mut table = String::new();
+        let row = |first: &str, rest: &[String]| {
+            let mut line = format!("| {:1$} |", first, solver_width);
+            for (cell, width) in rest.iter().zip(&input_widths) {
+                write!(line, " {:1$} |", cell, width).unwrap();
+            }
+            line
+        };
+        let header = self.inputs.iter().map(|it| it.to_string()).collect_vec();
+        writeln!(table, "{}", row("solver", &header)).unwrap();
+        let separator = input_widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect_vec();
+        writeln!(table, "{}", row(&"-".repeat(solver_width), &separator)).unwrap();
+        for (solver, cells) in self.solvers.iter().zip(&cells) {
+            writeln!(table, "{}", row(solver, cells)).unwrap();
+        }
+        table
+    }
+
+    fn raw_measurements(&self) -> String {
+        let mut list = String::new();
+        for solver in &self.solvers {
+            writeln!(list, "- {}", solver).unwrap();
+            for input in &self.inputs {
+                let value = match self.get(solver, input) {
+                    Some(measurement) => measurement.raw(),
+                    None => "todo".to_string(),
+                };
+                writeln!(list, "  - {}: {}", input, value).unwrap();
+            }
+        }
+        list
+    }
+}
+
+/// How the runtime of a solver on an input changed between two benchmarks.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub input: String,
+    pub old: Outcome,
+    pub new: Outcome,
+    /// How many times faster the new median is than the old one.
+    pub speedup: Option<f64>,
+    pub interval: Option<Interval>,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Faster,
+    Unchanged,
+    Slower,
+    /// Slower by more than the threshold, or it doesn't solve the input
+    /// anymore.
+    Regression,
+}
+
+/// Compares the measurements of each input. If both results contain only a
+/// single solver, those are compared with each other, so `old` can measure
+/// `fxhashmap` and `new` `better_vecs`. Otherwise, measurements are matched by
+/// solver and input. A slowdown only counts as a regression if the whole
+/// confidence interval is beyond the threshold, like 0.05 for 5 %.
+pub fn compare(old: &BenchResults, new: &BenchResults, threshold: f64) -> Vec<Comparison> {
+    let single_solver =
+        |results: &BenchResults| results.measurements.iter().map(|it| &it.solver).all_equal();
+    let match_solvers = !(single_solver(old) && single_solver(new));
+    new.measurements
+        .iter()
+        .filter_map(|new| {
+            let old = old.measurements.iter().rev().find(|old| {
+                old.input == new.input && (!match_solvers || old.solver == new.solver)
+            })?;
+            Some(compare_measurements(old, new, threshold))
+        })
+        .collect()
+}
+
+fn compare_measurements(old: &Measurement, new: &Measurement, threshold: f64) -> Comparison {
+    let input = if old.solver == new.solver {
+        format!("{} {}", new.solver, new.input)
+    } else {
+        new.input.clone()
+    };
+    let comparison = |speedup, interval, verdict| Comparison {
+        input: input.clone(),
+        old: old.outcome.clone(),
+        new: new.outcome.clone(),
+        speedup,
+        interval,
+        verdict,
+    };
+    let is_solved = |it: &Measurement| it.outcome == Outcome::Solved && !it.samples.is_empty();
+    match (is_solved(old), is_solved(new)) {
+        (true, true) => {}
+        (true, false) => return comparison(None, None, Verdict::Regression),
+        (false, true) => return comparison(None, None, Verdict::Faster),
+        (false, false) => return comparison(None, None, Verdict::Unchanged),
+    }
+
+    let old_samples = old.samples.iter().map(|it| *it as f64).collect_vec();
+    let new_samples = new.samples.iter().map(|it| *it as f64).collect_vec();
+    let ratio = stats::bootstrap_median_ratio(&new_samples, &old_samples, CONFIDENCE);
+    let speedup = old.summary.as_ref().unwrap().median / new.summary.as_ref().unwrap().median;
+    let verdict = if ratio.contains(1.0) {
+        Verdict::Unchanged
+    } else if ratio.low > 1.0 {
+        Verdict::Faster
+    } else if 1.0 / ratio.high > 1.0 + threshold {
+        Verdict::Regression
+    } else {
+        Verdict::Slower
+    };
+    comparison(Some(speedup), Some(ratio), verdict)
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.input)?;
+        match (self.speedup, self.interval) {
+            (Some(speedup), Some(interval)) => write!(
+                f,
+                "speedup {:.3}x ({:.0} % CI {:.3}x – {:.3}x)",
+                speedup,
+                CONFIDENCE * 100.0,
+                interval.low,
+                interval.high
+            )?,
+            _ => write!(f, "{} -> {}", self.old, self.new)?,
+        }
+        let verdict = match self.verdict {
+            Verdict::Faster => "faster",
+            Verdict::Unchanged => "no significant change",
+            Verdict::Slower => "slower",
+            Verdict::Regression => "REGRESSION",
+        };
+        write!(f, ", {}", verdict)
+    }
+}
+
+/// How a benchmark running in a child process ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChildExit {
+    Success,
+    Timeout,
+    OutOfMemory,
+    Failed(String),
+}
+
+/// Runs the command in a child process and kills it after the time limit. The
+/// memory limit caps the address space of the child, so allocations beyond it
+/// fail and abort the child instead of making the whole machine swap.
+pub fn run_limited(
+    command: &mut Command,
+    time_limit: Option<Duration>,
+    memory_limit: Option<usize>,
+) -> ChildExit {
+    if let Some(bytes) = memory_limit {
+        limit_memory(command, bytes);
+    }
+    let mut child = match command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => return ChildExit::Failed(format!("Couldn't start: {}", err)),
+    };
+    // Read stderr concurrently, otherwise a chatty child blocks once the pipe
+    // is full.
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr = thread::spawn(move || {
+        let mut output = String::new();
+        stderr.read_to_string(&mut output).ok();
+        output
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(err) => return ChildExit::Failed(format!("Couldn't wait: {}", err)),
+        }
+        if time_limit.is_some_and(|limit| start.elapsed() > limit) {
+            child.kill().ok();
+            child.wait().ok();
+            return ChildExit::Timeout;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let stderr = stderr.join().unwrap_or_default();
+
+    if status.success() {
+        ChildExit::Success
+    } else if stderr.contains("memory allocation of") || was_killed(&status) {
+        // Either an allocation failed because of the memory limit or the
+        // operating system killed the child because memory ran out.
+        ChildExit::OutOfMemory
+    } else {
+        let message = stderr
+            .lines()
+            .rfind(|line| !line.trim().is_empty())
+            .map_or_else(|| status.to_string(), |line| line.trim().to_string());
+        ChildExit::Failed(message)
+    }
+}
+
+#[cfg(unix)]
+fn limit_memory(command: &mut Command, bytes: usize) {
+    use std::os::unix::process::CommandExt;
+
+    let limit = libc::rlimit {
+        rlim_cur: bytes as libc::rlim_t,
+        rlim_max: bytes as libc::rlim_t,
+    };
+    // Safety: Between fork and exec, only setrlimit is called, which is
+    // async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+}
+#[cfg(not(unix))]
+fn limit_memory(_: &mut Command, _: usize) {
+    println!("WARNING: Memory limits are only supported on Unix.");
+}
+
+#[cfg(unix)]
+fn was_killed(status: &std::process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(libc::SIGKILL)
+}
+#[cfg(not(unix))]
+fn was_killed(_: &std::process::ExitStatus) -> bool {
+    false
+}
+
+const TABLE_MARKERS: (&str, &str) = ("<!-- measurements -->", "<!-- end measurements -->");
+const RAW_MARKERS: (&str, &str) = ("<!-- raw measurements -->", "<!-- end raw measurements -->");
+
+/// The measurement table and the raw measurement list in Markdown.
+pub fn report(results: &[BenchResults]) -> String {
+    let report = Report::new(results);
+    format!("{}\n{}", report.table(), report.raw_measurements())
+}
+
+/// Replaces the measurement table and the raw measurement list in the README,
+/// which are surrounded by HTML comments.
+pub fn update_readme(readme: &str, results: &[BenchResults]) -> Result<String, String> {
+    let report = Report::new(results);
+    let readme = replace_between(readme, TABLE_MARKERS, &report.table())?;
+    replace_between(&readme, RAW_MARKERS, &report.raw_measurements())
+}
+
+fn replace_between(
+    text: &str,
+    (start, end): (&str, &str),
+    content: &str,
+) -> Result<String, String> {
+    let missing = || format!("The README doesn't contain {} and {}.", start, end);
+    let (before, rest) = text.split_once(start).ok_or_else(missing)?;
+    let (_, after) = rest.split_once(end).ok_or_else(missing)?;
+    Ok(format!("{}{}\n{}{}{}", before, start, content, end, after))
+}
+
+pub fn format_duration(mut value: f64) -> String {
+    let units = ["ns", "us", "ms", "s"];
+    let mut magnitude = 0;
+    while value > 1000.0 && magnitude < units.len() - 1 {
+        magnitude += 1;
+        value /= 1000.0;
+    }
+    format!("{:.2} {}", value, units[magnitude])
+}
+
+pub fn format_bytes(mut value: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut magnitude = 0;
+    while value > 1024.0 && magnitude < units.len() - 1 {
+        magnitude += 1;
+        value /= 1024.0;
+    }
+    format!("{:.2} {}", value, units[magnitude])
+}
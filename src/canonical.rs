@@ -0,0 +1,199 @@
+//! Canonical forms of Kakuros, so that the same Kakuro is recognized even if
+//! it's rotated, reflected or surrounded by superfluous walls.
+//!
+//! A Kakuro is first turned into a grid of squares in which each empty cell
+//! knows the sums of its runs. Unlike the sums of a board, which have to be in
+//! the wall before their run, such a grid stays valid under all rotations and
+//! reflections. Transposing it swaps the horizontal and vertical sums. Each of
+//! the eight variants is turned back into a board by putting the sums into the
+//! walls before their runs, adding a row or column of walls at the top or left
+//! if needed. Rows and columns of walls without sums at the borders are
+//! removed. The canonical form is the variant with the smallest key.
+
+use crate::board::{Board, Cell, Value};
+use itertools::Itertools;
+
+#[derive(Debug, Clone, Copy)]
+enum Square {
+    Wall,
+    Empty {
+        horizontal_sum: Option<Value>,
+        vertical_sum: Option<Value>,
+    },
+}
+
+impl Board {
+    /// The canonical form of the Kakuro. Kakuros that only differ by their
+    /// orientation or by walls without sums at the borders have the same one.
+    pub fn canonical(&self) -> Board {
+        let mut squares = squares(self);
+        let mut smallest = to_board(&squares);
+        for i in 0..8 {
+            squares = if i == 4 {
+                transpose(&squares)
+            } else {
+                rotate(&squares)
+            };
+            let candidate = to_board(&squares);
+            if key(&candidate) < key(&smallest) {
+                smallest = candidate;
+            }
+        }
+        smallest
+    }
+
+    /// A hash of the canonical form as 16 hex digits. It's the 64-bit FNV-1a
+    /// hash of the key of the canonical form, so it stays the same across
+    /// versions of Rust and of this tool.
+    pub fn content_hash(&self) -> String {
+        let hash = key(&self.canonical())
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+            });
+        format!("{:016x}", hash)
+    }
+}
+
+/// A compact text representation of the board that's used to compare
+/// variants: one line per row, with `_` for empty cells and walls written as
+/// `vertical\horizontal`.
+fn key(board: &Board) -> String {
+    let sum = |sum: &Option<Value>| sum.map(|it| it.to_string()).unwrap_or_default();
+    board
+        .cells
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| match cell {
+                    Cell::Wall {
+                        vertical_sum,
+                        horizontal_sum,
+                    } => format!("{}\\{}", sum(vertical_sum), sum(horizontal_sum)),
+                    Cell::Empty => "_".to_string(),
+                })
+                .join(" ")
+        })
+        .join("\n")
+}
+
+/// The squares of the board. Rows shorter than others are filled up with
+/// walls.
+fn squares(board: &Board) -> Vec<Vec<Square>> {
+    let width = board.cells.iter().map(|row| row.len()).max().unwrap_or(0);
+    let is_empty = |x: usize, y: usize| matches!(board.cells[y].get(x), Some(Cell::Empty));
+    let sums = |x: usize, y: usize| match board.cells[y].get(x) {
+        Some(Cell::Wall {
+            vertical_sum,
+            horizontal_sum,
+        }) => (*vertical_sum, *horizontal_sum),
+        _ => (None, None),
+    };
+    (0..board.cells.len())
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    if !is_empty(x, y) {
+                        return Square::Wall;
+                    }
+                    let start_x = (0..x).rev().find(|x| !is_empty(*x, y));
+                    let start_y = (0..y).rev().find(|y| !is_empty(x, *y));
+                    Square::Empty {
+                        horizontal_sum: start_x.and_then(|start| sums(start, y).1),
+                        vertical_sum: start_y.and_then(|start| sums(x, start).0),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Turns the squares into a board with the sums in the walls before their
+/// runs and without superfluous walls at the borders.
+fn to_board(squares: &[Vec<Square>]) -> Board {
+    // Surround the squares by walls at the top and left, which can hold the
+    // sums of runs that start at the border.
+    let width = squares.first().map(|row| row.len()).unwrap_or(0) + 1;
+    let square = |x: usize, y: usize| match (x, y) {
+        (0, _) | (_, 0) => Square::Wall,
+        _ => squares
+            .get(y - 1)
+            .and_then(|row| row.get(x - 1))
+            .copied()
+            .unwrap_or(Square::Wall),
+    };
+    let mut cells = (0..=squares.len())
+        .map(|y| {
+            (0..width)
+                .map(|x| match square(x, y) {
+                    Square::Empty { .. } => Cell::Empty,
+                    Square::Wall => Cell::Wall {
+                        vertical_sum: match square(x, y + 1) {
+                            Square::Empty { vertical_sum, .. } => vertical_sum,
+                            Square::Wall => None,
+                        },
+                        horizontal_sum: match square(x + 1, y) {
+                            Square::Empty { horizontal_sum, .. } => horizontal_sum,
+                            Square::Wall => None,
+                        },
+                    },
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+
+    let is_bare = |cell: &Cell| {
+        matches!(
+            cell,
+            Cell::Wall {
+                vertical_sum: None,
+                horizontal_sum: None,
+            }
+        )
+    };
+    while cells.len() > 1 && cells[0].iter().all(is_bare) {
+        cells.remove(0);
+    }
+    while cells.len() > 1 && cells[cells.len() - 1].iter().all(is_bare) {
+        cells.pop();
+    }
+    while cells[0].len() > 1 && cells.iter().all(|row| is_bare(&row[0])) {
+        for row in &mut cells {
+            row.remove(0);
+        }
+    }
+    while cells[0].len() > 1 && cells.iter().all(|row| is_bare(&row[row.len() - 1])) {
+        for row in &mut cells {
+            row.pop();
+        }
+    }
+    Board { cells }
+}
+
+/// Rotates the squares by 90 degrees clockwise, which turns horizontal runs
+/// into vertical ones.
+fn rotate(squares: &[Vec<Square>]) -> Vec<Vec<Square>> {
+    (0..squares.first().map(|row| row.len()).unwrap_or(0))
+        .map(|x| squares.iter().rev().map(|row| swap(row[x])).collect())
+        .collect()
+}
+
+fn transpose(squares: &[Vec<Square>]) -> Vec<Vec<Square>> {
+    (0..squares.first().map(|row| row.len()).unwrap_or(0))
+        .map(|x| squares.iter().map(|row| swap(row[x])).collect())
+        .collect()
+}
+
+/// Swaps the horizontal and vertical sum.
+fn swap(square: Square) -> Square {
+    match square {
+        Square::Wall => Square::Wall,
+        Square::Empty {
+            horizontal_sum,
+            vertical_sum,
+        } => Square::Empty {
+            horizontal_sum: vertical_sum,
+            vertical_sum: horizontal_sum,
+        },
+    }
+}
@@ -0,0 +1,301 @@
+//! Differential testing of the solvers: Random Kakuros are solved by several
+//! solvers and their solutions are compared. The solvers work very differently,
+//! so a bug in one of them usually makes it disagree with the others.
+//!
+//! Kakuros on which the solvers disagree are shrunk to a minimal one on which
+//! they still disagree. Shrinking works on the grid of digits the Kakuro was
+//! generated from, so the sums always stay consistent and the shrunk Kakuro has
+//! at least one solution.
+
+use crate::{
+    board::Board,
+    game::{Input, Output, Solution},
+    generate::{self, Cell},
+    solvers,
+};
+use itertools::Itertools;
+use rand::Rng;
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+    panic::{self, AssertUnwindSafe},
+};
+
+/// The answer of a single solver.
+pub enum Answer {
+    Solutions(BTreeSet<Solution>),
+    /// The solver returned a solution that doesn't satisfy the constraints.
+    Invalid(Solution),
+    Panicked(String),
+    /// The solver timed out or ran out of memory, so it can't be compared.
+    Skipped(solvers::Error),
+}
+
+impl Answer {
+    fn of(input: &Input, result: Result<Output, solvers::Error>) -> Self {
+        match result {
+            Ok(output) => match output.iter().find(|it| !input.is_solution(it)) {
+                Some(invalid) => Answer::Invalid(invalid.clone()),
+                None => Answer::Solutions(output.into_iter().collect()),
+            },
+            Err(error) => Answer::Skipped(error),
+        }
+    }
+
+    /// Whether two solvers with these answers agree. Invalid solutions and
+    /// panics never agree with anything.
+    fn agrees_with(&self, other: &Answer) -> bool {
+        match (self, other) {
+            (Answer::Solutions(a), Answer::Solutions(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+pub struct Comparison {
+    /// The compared solvers, grouped by their answer.
+    pub groups: Vec<(Vec<String>, Answer)>,
+    /// The solvers that timed out or ran out of memory.
+    pub skipped: Vec<(String, solvers::Error)>,
+}
+
+impl Comparison {
+    pub fn is_agreement(&self) -> bool {
+        self.groups.len() <= 1
+            && self
+                .groups
+                .iter()
+                .all(|(_, answer)| matches!(answer, Answer::Solutions(_)))
+    }
+
+    /// The solvers that weren't skipped.
+    pub fn compared_solvers(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .flat_map(|(solvers, _)| solvers.iter().cloned())
+            .collect()
+    }
+}
+
+/// Solves the Kakuro with all the given solvers and groups them by their
+/// answer. Panics of solvers are caught, so `solve` should be called with a
+/// panic hook that doesn't print anything.
+pub fn compare(
+    board: &Board,
+    solvers: &[String],
+    solve: &impl Fn(&str, &Input) -> Result<Output, solvers::Error>,
+) -> Comparison {
+    let input = board.to_input();
+    let mut groups: Vec<(Vec<String>, Answer)> = vec![];
+    let mut skipped = vec![];
+    for solver in solvers {
+        let answer = match panic::catch_unwind(AssertUnwindSafe(|| solve(solver, &input))) {
+            Ok(result) => Answer::of(&input, result),
+            Err(payload) => Answer::Panicked(
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|it| it.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default(),
+            ),
+        };
+        match answer {
+            Answer::Skipped(error) => skipped.push((solver.clone(), error)),
+            answer => match groups.iter_mut().find(|(_, it)| it.agrees_with(&answer)) {
+                Some((group, _)) => group.push(solver.clone()),
+                None => groups.push((vec![solver.clone()], answer)),
+            },
+        }
+    }
+    Comparison { groups, skipped }
+}
+
+/// A random grid of digits of at most the given width and height. Between a
+/// third and two thirds of the cells contain digits. If the digits don't fit,
+/// another grid is tried.
+pub fn random_grid(max_size: usize, rand: &mut impl Rng) -> Vec<Vec<Cell>> {
+    loop {
+        let width = rand.gen_range(1..=max_size);
+        let height = rand.gen_range(1..=max_size);
+        let num_cells = width * height;
+        let numbers = rand.gen_range(num_cells.div_ceil(3)..=(num_cells * 2).div_ceil(3));
+        if let Ok(grid) = generate::fill(width, height, numbers, rand) {
+            return grid;
+        }
+    }
+}
+
+/// Shrinks the grid as long as the Kakuro generated from it still fails:
+/// Removes rows and columns, turns digits into walls and lowers digits.
+/// Returns a grid on which no single one of these steps keeps it failing.
+pub fn shrink(mut grid: Vec<Vec<Cell>>, fails: impl Fn(&Board) -> bool) -> Vec<Vec<Cell>> {
+    while let Some(smaller) = smaller_grids(&grid)
+        .into_iter()
+        .find(|candidate| fails(&generate::to_board(candidate)))
+    {
+        grid = smaller;
+    }
+    grid
+}
+
+/// All grids that are one step smaller than the given one and that are still
+/// valid and contain a digit, roughly ordered by how much smaller they are.
+fn smaller_grids(grid: &[Vec<Cell>]) -> Vec<Vec<Vec<Cell>>> {
+    let height = grid.len();
+    let width = grid[0].len();
+    let mut candidates = vec![];
+    if height > 1 {
+        for y in 0..height {
+            let mut candidate = grid.to_vec();
+            candidate.remove(y);
+            candidates.push(candidate);
+        }
+    }
+    if width > 1 {
+        for x in 0..width {
+            let mut candidate = grid.to_vec();
+            for row in &mut candidate {
+                row.remove(x);
+            }
+            candidates.push(candidate);
+        }
+    }
+    let digits = (0..height)
+        .cartesian_product(0..width)
+        .filter_map(|(y, x)| match grid[y][x] {
+            Cell::Value(digit) => Some((x, y, digit)),
+            Cell::Wall => None,
+        })
+        .collect_vec();
+    for (x, y, _) in &digits {
+        let mut candidate = grid.to_vec();
+        candidate[*y][*x] = Cell::Wall;
+        candidates.push(candidate);
+    }
+    for (x, y, digit) in &digits {
+        for lower in 1..*digit {
+            let mut candidate = grid.to_vec();
+            candidate[*y][*x] = Cell::Value(lower);
+            candidates.push(candidate);
+        }
+    }
+    candidates
+        .into_iter()
+        .filter(|candidate| {
+            generate::is_board_valid(candidate)
+                && candidate
+                    .iter()
+                    .flatten()
+                    .any(|cell| matches!(cell, Cell::Value(_)))
+        })
+        .collect()
+}
+
+impl Display for Answer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let digits = |solution: &Solution| solution.iter().join("");
+        match self {
+            Answer::Solutions(solutions) => {
+                let plural = if solutions.len() == 1 { "" } else { "s" };
+                write!(f, "{} solution{}", solutions.len(), plural)?;
+                if !solutions.is_empty() && solutions.len() <= 10 {
+                    write!(f, ": {}", solutions.iter().map(digits).join(", "))?;
+                }
+                Ok(())
+            }
+            Answer::Invalid(solution) => write!(f, "invalid solution {}", digits(solution)),
+            Answer::Panicked(message) => write!(f, "panicked: {}", message),
+            Answer::Skipped(error) => write!(f, "skipped: {}", error),
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (solvers, answer) in &self.groups {
+            writeln!(f, "{} ({})", answer, solvers.join(", "))?;
+        }
+        for (solver, error) in &self.skipped {
+            writeln!(f, "{} skipped: {}", solver, error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board::ParseBoard, solvers::Monitor};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const CORRECT_SOLVERS: [&str; 6] = [
+        "no_alloc",
+        "only_check_changes",
+        "divide",
+        "better_vecs",
+        "partitioned",
+        "tree_decomposition",
+    ];
+
+    fn solve(solver: &str, input: &Input) -> Result<Output, solvers::Error> {
+        let monitor = Monitor::unlimited();
+        let budget = solvers::MemoryBudget::unlimited();
+        match solver {
+            "gradual" => solvers::gradual::solve(input, &monitor),
+            "sum_table" => solvers::sum_table::solve(input, &monitor),
+            "no_alloc" => solvers::no_alloc::solve(input, &monitor),
+            "only_check_changes" => solvers::only_check_changes::solve(input, &monitor),
+            "divide" => solvers::divide::solve_within(input, &budget, &monitor),
+            "better_vecs" => solvers::better_vecs::solve_within(input, &budget, &monitor),
+            "partitioned" => {
+                solvers::earlier_anchor::solve_partitioned_within(input, &budget, &monitor)
+            }
+            "tree_decomposition" => solvers::tree_decomposition::solve(input, &monitor),
+            _ => panic!("Unknown solver {}.", solver),
+        }
+    }
+
+    fn solver_names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|it| it.to_string()).collect()
+    }
+
+    #[test]
+    fn shrinks_to_a_minimal_failing_grid() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let grid = random_grid(5, &mut rng);
+        let shrunk = shrink(grid, |board| board.to_input().num_cells >= 2);
+        let board = generate::to_board(&shrunk);
+        assert_eq!(board.to_input().num_cells, 2);
+        assert!(smaller_grids(&shrunk)
+            .iter()
+            .all(|candidate| generate::to_board(candidate).to_input().num_cells < 2));
+    }
+
+    #[test]
+    fn finds_invalid_solutions_of_known_wrong_solvers() {
+        let boards = [
+            "\\ 1\\\n\\1 _",
+            "\\ 8\\ 4\\ \\\n\\4 _ _ 5\\\n\\8 _ _ _\n\\5 _ \\ \\",
+        ];
+        for (board, wrong_solver) in boards.iter().zip(["gradual", "sum_table"]) {
+            let board = board.parse_board().unwrap();
+            let comparison = compare(&board, &solver_names(&["no_alloc", wrong_solver]), &solve);
+            assert!(!comparison.is_agreement());
+            assert!(comparison.groups.iter().any(|(solvers, answer)| {
+                solvers == &[wrong_solver] && matches!(answer, Answer::Invalid(_))
+            }));
+        }
+    }
+
+    #[test]
+    fn correct_solvers_agree_on_random_kakuros() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let solvers = solver_names(&CORRECT_SOLVERS);
+        for _ in 0..50 {
+            let board = generate::to_board(&random_grid(5, &mut rng));
+            let comparison = compare(&board, &solvers, &solve);
+            assert!(comparison.is_agreement(), "{}\n{}", board, comparison);
+            assert!(comparison.skipped.is_empty());
+        }
+    }
+}
@@ -21,6 +21,8 @@ pub struct Constraint {
 pub struct Input {
     pub num_cells: usize,
     pub constraints: Vec<Constraint>,
+    /// Runs without a sum, whose cells only need to have different digits.
+    pub all_different: Vec<Vec<usize>>,
 }
 
 pub type Solution = Vec<Value>;
@@ -116,14 +118,95 @@ impl Board {
             }
         }
 
+        let cell = |x: usize, y: usize| self.cells.get(y).and_then(|line| line.get(x));
+        let run = |mut x: usize, mut y: usize, (dx, dy): (usize, usize)| {
+            let mut cells = vec![];
+            while let Some(Cell::Empty) = cell(x, y) {
+                cells.push(mapping[&(x, y)]);
+                x += dx;
+                y += dy;
+            }
+            cells
+        };
+        // Runs that start at a wall without a sum in their direction only
+        // need different digits. Runs at the border of the board have no wall
+        // before them and stay unconstrained.
+        let mut all_different = vec![];
+        for (x, y) in self.cell_positions() {
+            let before_horizontal = x.checked_sub(1).and_then(|x| cell(x, y));
+            let before_vertical = y.checked_sub(1).and_then(|y| cell(x, y));
+            if let Some(Cell::Wall {
+                horizontal_sum: None,
+                ..
+            }) = before_horizontal
+            {
+                all_different.push(run(x, y, (1, 0)));
+            }
+            if let Some(Cell::Wall {
+                vertical_sum: None, ..
+            }) = before_vertical
+            {
+                all_different.push(run(x, y, (0, 1)));
+            }
+        }
+        all_different.retain(|cells| cells.len() > 1);
+
         Input {
             num_cells: mapping.len(),
             constraints,
+            all_different,
+        }
+    }
+
+    /// The coordinates of the cells of the input, in the same order as they
+    /// are numbered by `to_input`.
+    pub fn cell_positions(&self) -> Vec<(usize, usize)> {
+        let mut positions = vec![];
+        for (y, line) in self.cells.iter().enumerate() {
+            for (x, cell) in line.iter().enumerate() {
+                if let Cell::Empty = cell {
+                    positions.push((x, y));
+                }
+            }
         }
+        positions
+    }
+
+    /// The coordinates of the walls holding the sums of the constraints of the
+    /// input, in the same order as `to_input` creates the constraints.
+    pub fn clue_positions(&self) -> Vec<(usize, usize)> {
+        let mut positions = vec![];
+        for (y, line) in self.cells.iter().enumerate() {
+            for (x, cell) in line.iter().enumerate() {
+                if let Cell::Wall {
+                    vertical_sum,
+                    horizontal_sum,
+                } = cell
+                {
+                    if horizontal_sum.is_some() {
+                        positions.push((x, y));
+                    }
+                    if vertical_sum.is_some() {
+                        positions.push((x, y));
+                    }
+                }
+            }
+        }
+        positions
     }
 }
 
 impl Input {
+    /// The runs with their sums, first the constraints and then the runs
+    /// without a sum.
+    pub fn runs(&self) -> Vec<(&[usize], Option<Value>)> {
+        self.constraints
+            .iter()
+            .map(|constraint| (&constraint.cells[..], Some(constraint.sum)))
+            .chain(self.all_different.iter().map(|cells| (&cells[..], None)))
+            .collect()
+    }
+
     pub fn is_solution(&self, solution: &Solution) -> bool {
         solution.len() == self.num_cells
             && solution.iter().all(|number| (1..=9).contains(number))
@@ -131,6 +214,31 @@ impl Input {
                 .constraints
                 .iter()
                 .all(|constraint| constraint.is_solution(solution))
+            && self.has_different_digits(solution)
+    }
+
+    /// Whether the runs without a sum have different digits in the solution.
+    /// Solvers that only look at the constraints use this to filter their
+    /// solutions.
+    pub fn has_different_digits(&self, solution: &Solution) -> bool {
+        self.all_different
+            .iter()
+            .all(|cells| cells.iter().map(|i| solution[*i]).all_unique())
+    }
+
+    /// The runs without a sum that only contain cells of the part, with the
+    /// cells mapped to the indizes in the part. Used by solvers that split
+    /// inputs into parts.
+    pub fn all_different_within(
+        &self,
+        is_in_part: impl Fn(usize) -> bool,
+        index_mapping: &[usize],
+    ) -> Vec<Vec<usize>> {
+        self.all_different
+            .iter()
+            .filter(|cells| cells.iter().all(|cell| is_in_part(*cell)))
+            .map(|cells| cells.iter().map(|cell| index_mapping[*cell]).collect())
+            .collect()
     }
 }
 impl Constraint {
@@ -145,3 +253,21 @@ impl Constraint {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::board::ParseBoard;
+
+    #[test]
+    fn runs_after_walls_without_sums_need_different_digits() {
+        let input = "\\ \\ \\\n\\ _ _".parse_board().unwrap().to_input();
+        assert_eq!(input.all_different, vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn runs_at_the_border_are_unconstrained() {
+        let input = "_ _\n_ _".parse_board().unwrap().to_input();
+        assert!(input.constraints.is_empty());
+        assert!(input.all_different.is_empty());
+    }
+}
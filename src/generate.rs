@@ -1,15 +1,22 @@
 use itertools::Itertools;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::{Duration, Instant};
 
-use crate::board::{self, Value};
+use crate::{
+    board::{self, Value},
+    layout,
+    rating::{self, Difficulty, Rating},
+    solvers::{CancellationToken, Monitor},
+    uniqueness,
+};
 
 #[derive(Debug, Clone, Copy)]
-enum Cell {
+pub enum Cell {
     Wall,
     Value(Value),
 }
 
-fn is_board_valid(board: &[Vec<Cell>]) -> bool {
+pub fn is_board_valid(board: &[Vec<Cell>]) -> bool {
     for row in board {
         if !is_valid(&row) {
             return false;
@@ -62,47 +69,104 @@ struct Wall {
     vertical_sum: Option<Value>,
 }
 
-pub fn generate(width: usize, height: usize, numbers: usize) -> board::Board {
-    let mut board = {
-        let mut cells = vec![];
-        for _y in 0..height {
-            let mut row = vec![];
-            for _x in 0..width {
-                row.push(Cell::Wall);
-            }
-            cells.push(row);
-        }
-        cells
-    };
-
-    let mut rand = rand::thread_rng();
-    let mut tries: usize = 0;
-    fn num_values(board: &[Vec<Cell>]) -> usize {
-        board
-            .iter()
-            .flatten()
-            .filter(|cell| matches!(cell, Cell::Value(_)))
-            .count()
-    }
-    while num_values(&board) < numbers {
+/// Generates a Kakuro from a seed. The same seed and parameters always result
+/// in the same Kakuro, as long as the version of the `rand` crate stays the
+/// same.
+pub fn generate(
+    width: usize,
+    height: usize,
+    numbers: usize,
+    seed: u64,
+) -> Result<board::Board, String> {
+    generate_with_rng(width, height, numbers, &mut StdRng::seed_from_u64(seed))
+}
+
+pub fn generate_with_rng(
+    width: usize,
+    height: usize,
+    numbers: usize,
+    rand: &mut impl Rng,
+) -> Result<board::Board, String> {
+    Ok(to_board(&fill(width, height, numbers, rand)?))
+}
+
+/// Randomly fills a grid with the given number of digits, so that no digit
+/// appears twice in a run. Fails if the digits placed so far leave no room for
+/// the remaining ones, which always happens if there are too many of them.
+pub fn fill(
+    width: usize,
+    height: usize,
+    numbers: usize,
+    rand: &mut impl Rng,
+) -> Result<Vec<Vec<Cell>>, String> {
+    let mut board = vec![vec![Cell::Wall; width]; height];
+    let mut placed = 0;
+    let mut failed_attempts = 0;
+    while placed < numbers {
         let x = rand.gen_range(0..width);
         let y = rand.gen_range(0..height);
         let digit: Value = rand.gen_range(1..=9);
 
-        if !matches!(board[y][x], Cell::Wall) {
-            continue;
-        }
-        board[y][x] = Cell::Value(digit);
-        if !is_board_valid(&board) {
-            // println!("Board invalid: {:?}", board);
-            board[y][x] = Cell::Wall;
+        if matches!(board[y][x], Cell::Wall) && can_place(&board, x, y, digit) {
+            board[y][x] = Cell::Value(digit);
+            placed += 1;
+            failed_attempts = 0;
+        } else {
+            failed_attempts += 1;
         }
-
-        tries += 1;
-        if tries % 1000 == 0 {
-            println!("{}/{} numbers done", num_values(&board), numbers);
+        // After many failed attempts in a row, check whether any digit fits
+        // at all, so that hopeless grids don't loop forever.
+        if failed_attempts == width * height * 9 {
+            failed_attempts = 0;
+            let fits = (0..height).any(|y| {
+                (0..width).any(|x| {
+                    matches!(board[y][x], Cell::Wall)
+                        && (1..=9).any(|digit| can_place(&board, x, y, digit))
+                })
+            });
+            if !fits {
+                return Err(format!(
+                    "Couldn't place more than {} of the {} digits without repeating digits in a run.",
+                    placed, numbers
+                ));
+            }
         }
     }
+    Ok(board)
+}
+
+/// Whether the digit can replace the wall at the position without any digit
+/// appearing twice in a run. The wall may join two runs into one, so their
+/// digits must differ as well. Only looks at the cells of the two runs the
+/// position becomes part of, so the rest of the grid is assumed to be valid.
+fn can_place(board: &[Vec<Cell>], x: usize, y: usize, digit: Value) -> bool {
+    let row = &board[y];
+    let left = digits_until_wall(row[..x].iter().rev());
+    let right = digits_until_wall(row[x + 1..].iter());
+    let above = digits_until_wall(board[..y].iter().rev().map(|row| &row[x]));
+    let below = digits_until_wall(board[y + 1..].iter().map(|row| &row[x]));
+    let bit = 1 << digit;
+    left & right == 0
+        && (left | right) & bit == 0
+        && above & below == 0
+        && (above | below) & bit == 0
+}
+
+/// The digits of the cells before the first wall, as bits.
+fn digits_until_wall<'a>(cells: impl Iterator<Item = &'a Cell>) -> u16 {
+    cells
+        .map_while(|cell| match cell {
+            Cell::Value(digit) => Some(1 << digit),
+            Cell::Wall => None,
+        })
+        .fold(0, |digits, bit| digits | bit)
+}
+
+/// Turns a filled grid into a Kakuro whose sums are the ones of the digits. The
+/// Kakuro gets an additional row and column of walls at the top and left.
+pub fn to_board(board: &[Vec<Cell>]) -> board::Board {
+    let height = board.len();
+    let width = board[0].len();
 
     // Turn board into wall grid.
     let mut walls = vec![];
@@ -177,3 +241,54 @@ pub fn generate(width: usize, height: usize, numbers: usize) -> board::Board {
     }
     board::Board { cells }
 }
+
+/// Why candidates were rejected while generating a Kakuro of some difficulty.
+#[derive(Debug, Default)]
+pub struct Rejections {
+    /// Filling the layout didn't result in a unique solution in time.
+    pub not_unique: usize,
+    pub too_easy: usize,
+    /// Includes candidates that couldn't be rated in time.
+    pub too_hard: usize,
+}
+
+/// How long a single candidate may take to be filled and rated. Slow
+/// candidates rarely succeed, so it's kept short.
+const CANDIDATE_TIME: Duration = Duration::from_secs(1);
+
+/// Generates layouts, fills them so that they have a unique solution and rates
+/// them until one has the given difficulty.
+pub fn generate_with_difficulty(
+    layout: &layout::Options,
+    difficulty: Difficulty,
+    timeout: Duration,
+    rand: &mut impl Rng,
+    rejections: &mut Rejections,
+) -> Result<(Vec<Vec<Cell>>, Rating), String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(format!(
+                "Couldn't generate a Kakuro of difficulty {} within {:?}.",
+                difficulty, timeout
+            ));
+        }
+        let monitor = Monitor::new(
+            CancellationToken::new(),
+            Some(remaining.min(CANDIDATE_TIME)),
+        );
+        let grid = match uniqueness::fill_uniquely(&layout.generate(rand)?, rand, &monitor) {
+            Ok(grid) => grid,
+            Err(_) => {
+                rejections.not_unique += 1;
+                continue;
+            }
+        };
+        match rating::rate(&to_board(&grid).to_input(), &monitor) {
+            Ok(Some(rating)) if rating.difficulty == difficulty => return Ok((grid, rating)),
+            Ok(Some(rating)) if rating.difficulty < difficulty => rejections.too_easy += 1,
+            Ok(_) | Err(_) => rejections.too_hard += 1,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! This module exports the graph of a Kakuro for looking at it in other tools.
+//! Cells and runs are both nodes and each run is connected to its cells. The
+//! nodes are placed at their position on the board: cells where they are and
+//! runs next to the wall that holds their sum.
+//!
+//! Cells can be colored by a split into a red and a blue part. Runs that
+//! contain cells of both parts are the connecting constraints of the split.
+
+use crate::{
+    board::Board,
+    game::{Input, Value},
+    partition,
+};
+use itertools::Itertools;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter, Write},
+};
+
+pub struct Graph {
+    cells: Vec<GraphCell>,
+    runs: Vec<Run>,
+    /// For each cell, whether it's red. `None` if the graph is not split.
+    colors: Option<Vec<bool>>,
+}
+
+struct GraphCell {
+    x: f64,
+    y: f64,
+}
+
+struct Run {
+    x: f64,
+    y: f64,
+    sum: Value,
+    cells: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub num_cells: usize,
+    pub num_runs: usize,
+    /// The sizes of the connected components, biggest first.
+    pub component_sizes: Vec<usize>,
+    /// Cells that disconnect their component if they are removed.
+    pub articulation_cells: Vec<usize>,
+    /// How many runs there are of each length.
+    pub run_lengths: BTreeMap<usize, usize>,
+    /// How many runs contain cells of both parts, if the graph is split.
+    pub connections: Option<usize>,
+}
+
+impl Graph {
+    pub fn new(board: &Board, input: &Input) -> Self {
+        let cell_positions = board.cell_positions();
+        let cells = cell_positions
+            .iter()
+            .map(|(x, y)| GraphCell {
+                x: *x as f64,
+                y: *y as f64,
+            })
+            .collect();
+        let runs = board
+            .clue_positions()
+            .into_iter()
+            .zip(&input.constraints)
+            .map(|((x, y), constraint)| {
+                // Horizontal runs start in the same row as their wall. Both
+                // runs of a wall are moved a bit towards their cells so that
+                // they don't overlap.
+                let is_horizontal = cell_positions[constraint.cells[0]].1 == y;
+                let (x, y) = if is_horizontal {
+                    (x as f64 + 0.3, y as f64)
+                } else {
+                    (x as f64, y as f64 + 0.3)
+                };
+                Run {
+                    x,
+                    y,
+                    sum: constraint.sum,
+                    cells: constraint.cells.clone(),
+                }
+            })
+            .collect();
+        Self {
+            cells,
+            runs,
+            colors: None,
+        }
+    }
+
+    pub fn with_colors(self, colors: Vec<bool>) -> Self {
+        Self {
+            colors: Some(colors),
+            ..self
+        }
+    }
+
+    fn is_connecting(&self, run: &Run) -> bool {
+        match &self.colors {
+            Some(colors) => !run.cells.iter().map(|cell| colors[*cell]).all_equal(),
+            None => false,
+        }
+    }
+
+    fn cell_color(&self, cell: usize) -> &'static str {
+        match &self.colors {
+            Some(colors) if colors[cell] => "#f4a6a6",
+            Some(_) => "#a6c8f4",
+            None => "white",
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let constraints = self
+            .runs
+            .iter()
+            .map(|run| run.cells.as_slice())
+            .collect_vec();
+        let num_cells = self.cells.len();
+        Stats {
+            num_cells,
+            num_runs: self.runs.len(),
+            component_sizes: partition::components(num_cells, &constraints)
+                .iter()
+                .map(|component| component.len())
+                .sorted_by_key(|size| std::cmp::Reverse(*size))
+                .collect(),
+            articulation_cells: partition::articulation_cells(num_cells, &constraints),
+            run_lengths: self
+                .runs
+                .iter()
+                .map(|run| run.cells.len())
+                .counts()
+                .into_iter()
+                .collect(),
+            connections: self
+                .colors
+                .as_ref()
+                .map(|colors| partition::num_connections(&constraints, colors)),
+        }
+    }
+
+    /// Exports the graph in the Graphviz format. The positions are pinned, so
+    /// render it with `neato` or `fdp` to keep the layout of the board.
+    pub fn to_dot(&self) -> String {
+        let articulation_cells = self.stats().articulation_cells;
+        let mut dot = String::new();
+        writeln!(dot, "graph kakuro {{").unwrap();
+        writeln!(dot, "  node [fontname=\"monospace\"];").unwrap();
+        for (i, cell) in self.cells.iter().enumerate() {
+            writeln!(
+                dot,
+                "  c{} [label=\"{}\", shape=box, style=filled, fillcolor=\"{}\", penwidth={}, pos=\"{},{}!\"];",
+                i,
+                i,
+                self.cell_color(i),
+                if articulation_cells.contains(&i) { 3 } else { 1 },
+                cell.x,
+                -cell.y
+            )
+            .unwrap();
+        }
+        for (i, run) in self.runs.iter().enumerate() {
+            writeln!(
+                dot,
+                "  r{} [label=\"{}\", shape=plaintext, pos=\"{},{}!\"];",
+                i, run.sum, run.x, -run.y
+            )
+            .unwrap();
+            let style = if self.is_connecting(run) {
+                "bold"
+            } else {
+                "solid"
+            };
+            for cell in &run.cells {
+                writeln!(dot, "  r{} -- c{} [style={}];", i, cell, style).unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// Exports the graph in the GraphML format. Nodes have their board
+    /// coordinates as `x` and `y` attributes.
+    pub fn to_graphml(&self) -> String {
+        let articulation_cells = self.stats().articulation_cells;
+        let mut xml = String::new();
+        writeln!(xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(
+            xml,
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+        )
+        .unwrap();
+        for (id, kind) in [
+            ("kind", "string"),
+            ("label", "string"),
+            ("x", "double"),
+            ("y", "double"),
+            ("color", "string"),
+            ("articulation", "boolean"),
+        ] {
+            writeln!(
+                xml,
+                "  <key id=\"{}\" for=\"node\" attr.name=\"{}\" attr.type=\"{}\"/>",
+                id, id, kind
+            )
+            .unwrap();
+        }
+        writeln!(
+            xml,
+            "  <key id=\"connecting\" for=\"edge\" attr.name=\"connecting\" attr.type=\"boolean\"/>"
+        )
+        .unwrap();
+        writeln!(xml, "  <graph id=\"kakuro\" edgedefault=\"undirected\">").unwrap();
+        for (i, cell) in self.cells.iter().enumerate() {
+            writeln!(xml, "    <node id=\"c{}\">", i).unwrap();
+            writeln!(xml, "      <data key=\"kind\">cell</data>").unwrap();
+            writeln!(xml, "      <data key=\"label\">{}</data>", i).unwrap();
+            writeln!(xml, "      <data key=\"x\">{}</data>", cell.x).unwrap();
+            writeln!(xml, "      <data key=\"y\">{}</data>", cell.y).unwrap();
+            writeln!(
+                xml,
+                "      <data key=\"color\">{}</data>",
+                self.cell_color(i)
+            )
+            .unwrap();
+            writeln!(
+                xml,
+                "      <data key=\"articulation\">{}</data>",
+                articulation_cells.contains(&i)
+            )
+            .unwrap();
+            writeln!(xml, "    </node>").unwrap();
+        }
+        for (i, run) in self.runs.iter().enumerate() {
+            writeln!(xml, "    <node id=\"r{}\">", i).unwrap();
+            writeln!(xml, "      <data key=\"kind\">run</data>").unwrap();
+            writeln!(xml, "      <data key=\"label\">{}</data>", run.sum).unwrap();
+            writeln!(xml, "      <data key=\"x\">{}</data>", run.x).unwrap();
+            writeln!(xml, "      <data key=\"y\">{}</data>", run.y).unwrap();
+            writeln!(xml, "    </node>").unwrap();
+        }
+        for (i, run) in self.runs.iter().enumerate() {
+            let is_connecting = self.is_connecting(run);
+            for cell in &run.cells {
+                writeln!(xml, "    <edge source=\"r{}\" target=\"c{}\">", i, cell).unwrap();
+                writeln!(
+                    xml,
+                    "      <data key=\"connecting\">{}</data>",
+                    is_connecting
+                )
+                .unwrap();
+                writeln!(xml, "    </edge>").unwrap();
+            }
+        }
+        writeln!(xml, "  </graph>").unwrap();
+        writeln!(xml, "</graphml>").unwrap();
+        xml
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} cells, {} runs", self.num_cells, self.num_runs)?;
+        writeln!(
+            f,
+            "{} connected components with {} cells",
+            self.component_sizes.len(),
+            self.component_sizes.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "{} articulation cells: {:?}",
+            self.articulation_cells.len(),
+            self.articulation_cells
+        )?;
+        if let Some(connections) = self.connections {
+            writeln!(f, "{} runs connect the red and blue part", connections)?;
+        }
+        write!(f, "Run lengths:")?;
+        let max_count = self.run_lengths.values().copied().max().unwrap_or(1);
+        for (length, count) in &self.run_lengths {
+            let bar = (count * 50).div_ceil(max_count);
+            write!(f, "\n{:>3}: {:>4} {}", length, count, "#".repeat(bar))?;
+        }
+        Ok(())
+    }
+}
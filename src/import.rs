@@ -1,4 +1,7 @@
-use crate::board::{Board, Cell, Value};
+use crate::{
+    board::{Board, Cell, Value},
+    game::Solution,
+};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
@@ -48,3 +51,29 @@ impl ImportJsonBoard for str {
         Ok(Board { cells })
     }
 }
+
+pub trait ImportJsonSolution {
+    fn import_json_solution(&self, board: &Board) -> Result<Solution, String>;
+}
+impl ImportJsonSolution for str {
+    /// Solutions from kakuros.com look like the boards, except that the empty
+    /// cells contain their digit instead of 0. The digits are returned in the
+    /// order in which `Board::to_input` numbers the cells of the board.
+    fn import_json_solution(&self, board: &Board) -> Result<Solution, String> {
+        let json: JsonBoard = serde_json::from_str(self).map_err(|err| format!("{:?}", err))?;
+        board
+            .cell_positions()
+            .into_iter()
+            .map(
+                |(x, y)| match json.board.get(y).and_then(|row| row.get(x)) {
+                    Some(digit @ 1..=9) => Ok(*digit as Value),
+                    Some(cell) => Err(format!(
+                        "The solution contains {} at ({}, {}) instead of a digit.",
+                        cell, x, y
+                    )),
+                    None => Err(format!("The solution doesn't contain ({}, {}).", x, y)),
+                },
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,400 @@
+//! Layout-first generation: Instead of placing digits at random positions like
+//! `generate::fill`, this first builds a pattern of walls that looks like the
+//! ones of published Kakuros and then fills its cells with digits. The pattern
+//! is symmetric, all runs have a minimum length, no run is longer than 9 cells
+//! and all cells are connected.
+
+use crate::{
+    board::{self, Board, Value},
+    generate::Cell,
+};
+use itertools::Itertools;
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    None,
+    /// The layout looks the same when rotated by 180°.
+    Rotational,
+    /// The right half mirrors the left half.
+    Mirror,
+}
+
+/// For each cell, whether it's empty (as opposed to a wall).
+pub type Layout = Vec<Vec<bool>>;
+
+const MAX_RUN: usize = 9;
+const ATTEMPTS: usize = 1000;
+/// Layouts whose density is this close to the target are good enough.
+const DENSITY_TOLERANCE: f64 = 0.02;
+/// Filling the layout with digits restarts after this many tried digits.
+const MAX_FILL_STEPS: usize = 100000;
+/// Filling the layout with digits gives up after this many restarts.
+const MAX_FILL_ATTEMPTS: usize = 100;
+
+/// What layouts to generate.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub width: usize,
+    pub height: usize,
+    /// The fraction of cells that should be empty.
+    pub density: f64,
+    pub symmetry: Symmetry,
+    pub min_run: usize,
+}
+
+impl Options {
+    pub fn generate(&self, rand: &mut impl Rng) -> Result<Layout, String> {
+        layout(
+            self.width,
+            self.height,
+            self.density,
+            self.symmetry,
+            self.min_run,
+            rand,
+        )
+    }
+}
+
+pub fn layout(
+    width: usize,
+    height: usize,
+    density: f64,
+    symmetry: Symmetry,
+    min_run: usize,
+    rand: &mut impl Rng,
+) -> Result<Layout, String> {
+    // Repairing a layout turns more cells into walls, so we aim for a higher
+    // density than the target and adjust the aim after each attempt.
+    let mut aim = density;
+    let mut best: Option<(f64, Layout)> = None;
+    for _ in 0..ATTEMPTS {
+        let layout = match attempt(width, height, aim, symmetry, min_run, rand) {
+            Some(layout) => layout,
+            None => continue,
+        };
+        let error = density_of(&layout) - density;
+        aim = (aim - error).clamp(0.0, 1.0);
+        if best
+            .as_ref()
+            .is_none_or(|(best_error, _)| error.abs() < best_error.abs())
+        {
+            best = Some((error, layout));
+        }
+        if error.abs() <= DENSITY_TOLERANCE {
+            break;
+        }
+    }
+    best.map(|(_, layout)| layout).ok_or_else(|| {
+        format!(
+            "Couldn't find a {}x{} layout with runs of {} to {} cells.",
+            width, height, min_run, MAX_RUN
+        )
+    })
+}
+
+fn density_of(layout: &Layout) -> f64 {
+    let num_cells = layout.iter().flatten().filter(|it| **it).count();
+    num_cells as f64 / (layout.len() * layout[0].len()) as f64
+}
+
+fn attempt(
+    width: usize,
+    height: usize,
+    density: f64,
+    symmetry: Symmetry,
+    min_run: usize,
+    rand: &mut impl Rng,
+) -> Option<Layout> {
+    let mut layout = vec![vec![true; width]; height];
+    let mut num_cells = width * height;
+    let mut positions = (0..width).cartesian_product(0..height).collect_vec();
+    positions.shuffle(rand);
+    for (x, y) in positions {
+        if num_cells as f64 / (width * height) as f64 <= density {
+            break;
+        }
+        num_cells -= set_wall(&mut layout, x, y, symmetry);
+    }
+    repair(&mut layout, symmetry, min_run, rand);
+    keep_largest_region(&mut layout, symmetry)?;
+    Some(layout)
+}
+
+/// Reads the layout of a template, which is a Kakuro whose walls don't need
+/// sums. Like in every Kakuro, its first row and column must be walls.
+pub fn from_template(template: &Board) -> Result<Layout, String> {
+    let is_empty = |cell: &board::Cell| matches!(cell, board::Cell::Empty);
+    let first_row = template.cells.first().ok_or("The template is empty.")?;
+    if first_row.iter().any(is_empty)
+        || template
+            .cells
+            .iter()
+            .any(|row| row.first().is_some_and(is_empty))
+    {
+        return Err("The first row and column of the template must be walls.".to_string());
+    }
+    let width = first_row.len();
+    if width < 2 || template.cells.len() < 2 || template.cells.iter().any(|row| row.len() != width)
+    {
+        return Err("The rows of the template must be equally long and contain cells.".to_string());
+    }
+    let layout = template.cells[1..]
+        .iter()
+        .map(|row| row[1..].iter().map(is_empty).collect_vec())
+        .collect_vec();
+    if !layout.iter().flatten().any(|it| *it) {
+        return Err("The template has no cells.".to_string());
+    }
+    if let Some(run) = runs(&layout).into_iter().find(|run| run.len() > MAX_RUN) {
+        let (x, y) = run[0];
+        return Err(format!(
+            "The run starting at ({}, {}) has {} cells, but runs can have at most {}.",
+            x + 1,
+            y + 1,
+            run.len(),
+            MAX_RUN
+        ));
+    }
+    Ok(layout)
+}
+
+/// The position of the cell that must look like the given one.
+fn counterpart(layout: &Layout, x: usize, y: usize, symmetry: Symmetry) -> (usize, usize) {
+    let width = layout[0].len();
+    let height = layout.len();
+    match symmetry {
+        Symmetry::None => (x, y),
+        Symmetry::Rotational => (width - 1 - x, height - 1 - y),
+        Symmetry::Mirror => (width - 1 - x, y),
+    }
+}
+
+/// Turns the cell and its symmetric counterpart into walls. Returns how many
+/// cells were empty before.
+fn set_wall(layout: &mut Layout, x: usize, y: usize, symmetry: Symmetry) -> usize {
+    let (other_x, other_y) = counterpart(layout, x, y, symmetry);
+    let mut num_changed = 0;
+    for (x, y) in [(x, y), (other_x, other_y)] {
+        if layout[y][x] {
+            layout[y][x] = false;
+            num_changed += 1;
+        }
+    }
+    num_changed
+}
+
+/// All horizontal and vertical runs of the layout, as lists of positions.
+fn runs(layout: &Layout) -> Vec<Vec<(usize, usize)>> {
+    let width = layout[0].len();
+    let height = layout.len();
+    let rows = (0..height).map(|y| (0..width).map(|x| (x, y)).collect_vec());
+    let columns = (0..width).map(|x| (0..height).map(|y| (x, y)).collect_vec());
+    rows.chain(columns)
+        .flat_map(|line| {
+            line.into_iter()
+                .group_by(|(x, y)| layout[*y][*x])
+                .into_iter()
+                .filter(|(is_empty, _)| *is_empty)
+                .map(|(_, run)| run.collect_vec())
+                .collect_vec()
+        })
+        .collect()
+}
+
+/// Turns cells into walls until all runs have a valid length. Runs that are
+/// too short are removed completely, runs that are too long are split.
+fn repair(layout: &mut Layout, symmetry: Symmetry, min_run: usize, rand: &mut impl Rng) {
+    loop {
+        let runs = runs(layout);
+        if let Some(run) = runs.iter().find(|run| run.len() < min_run) {
+            for (x, y) in run {
+                set_wall(layout, *x, *y, symmetry);
+            }
+        } else if let Some(run) = runs.iter().find(|run| run.len() > MAX_RUN) {
+            // If possible, split it so that both parts are long enough.
+            let splits = if run.len() > 2 * min_run {
+                &run[min_run..run.len() - min_run]
+            } else {
+                &run[..]
+            };
+            let (x, y) = *splits.choose(rand).unwrap();
+            set_wall(layout, x, y, symmetry);
+        } else {
+            return;
+        }
+    }
+}
+
+/// Turns all cells into walls that are not connected to the biggest region of
+/// cells. Fails if that would make the layout asymmetric or empty.
+fn keep_largest_region(layout: &mut Layout, symmetry: Symmetry) -> Option<()> {
+    let width = layout[0].len();
+    let height = layout.len();
+    let mut region_of = vec![vec![None; width]; height];
+    let mut region_sizes = vec![];
+    for (x, y) in (0..width).cartesian_product(0..height) {
+        if !layout[y][x] || region_of[y][x].is_some() {
+            continue;
+        }
+        let region = region_sizes.len();
+        let mut size = 0;
+        let mut stack = vec![(x, y)];
+        region_of[y][x] = Some(region);
+        while let Some((x, y)) = stack.pop() {
+            size += 1;
+            let neighbors = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (x, y) in neighbors {
+                if x < width && y < height && layout[y][x] && region_of[y][x].is_none() {
+                    region_of[y][x] = Some(region);
+                    stack.push((x, y));
+                }
+            }
+        }
+        region_sizes.push(size);
+    }
+    let largest = region_sizes.iter().position_max()?;
+    for (x, y) in (0..width).cartesian_product(0..height) {
+        if region_of[y][x] == Some(largest) {
+            let (other_x, other_y) = counterpart(layout, x, y, symmetry);
+            if region_of[other_y][other_x] != Some(largest) {
+                return None;
+            }
+        } else {
+            layout[y][x] = false;
+        }
+    }
+    Some(())
+}
+
+/// Fills the empty cells of the layout with digits so that no digit appears
+/// twice in a run. Always continues with the cell that has the fewest digits
+/// left, which are tracked for each run as a mask of used digits. Fails if
+/// none of `MAX_FILL_ATTEMPTS` attempts fills all cells.
+pub fn fill(layout: &Layout, rand: &mut impl Rng) -> Result<Vec<Vec<Cell>>, String> {
+    let runs = runs(layout);
+    let mut runs_of_cell = vec![vec![vec![]; layout[0].len()]; layout.len()];
+    for (i, run) in runs.iter().enumerate() {
+        for (x, y) in run {
+            runs_of_cell[*y][*x].push(i);
+        }
+    }
+    let cells = (0..layout.len())
+        .cartesian_product(0..layout[0].len())
+        .filter(|(y, x)| layout[*y][*x])
+        .map(|(y, x)| Filling {
+            x,
+            y,
+            runs: runs_of_cell[y][x].clone(),
+            digit: None,
+        })
+        .collect_vec();
+    for _ in 0..MAX_FILL_ATTEMPTS {
+        let mut cells = cells.clone();
+        let mut used = vec![0u16; runs.len()];
+        let mut steps = 0;
+        if fill_rec(&mut cells, &mut used, rand, &mut steps) {
+            let mut grid = vec![vec![Cell::Wall; layout[0].len()]; layout.len()];
+            for cell in cells {
+                grid[cell.y][cell.x] = Cell::Value(cell.digit.unwrap());
+            }
+            return Ok(grid);
+        }
+    }
+    Err(format!(
+        "Couldn't fill the layout with digits in {} attempts.",
+        MAX_FILL_ATTEMPTS
+    ))
+}
+
+#[derive(Clone)]
+struct Filling {
+    x: usize,
+    y: usize,
+    runs: Vec<usize>,
+    digit: Option<Value>,
+}
+
+impl Filling {
+    /// The digits that are not used in the runs of the cell yet.
+    fn candidates(&self, used: &[u16]) -> u16 {
+        self.runs
+            .iter()
+            .fold(0b11_1111_1110, |candidates, run| candidates & !used[*run])
+    }
+}
+
+fn fill_rec(
+    cells: &mut [Filling],
+    used: &mut [u16],
+    rand: &mut impl Rng,
+    steps: &mut usize,
+) -> bool {
+    let next = cells
+        .iter()
+        .enumerate()
+        .filter(|(_, cell)| cell.digit.is_none())
+        .min_by_key(|(_, cell)| cell.candidates(used).count_ones());
+    let (i, candidates) = match next {
+        Some((i, cell)) => (i, cell.candidates(used)),
+        None => return true,
+    };
+    let mut digits = (1..=9)
+        .filter(|digit| candidates & (1 << digit) != 0)
+        .collect_vec();
+    digits.shuffle(rand);
+    for digit in digits {
+        *steps += 1;
+        if *steps > MAX_FILL_STEPS {
+            return false;
+        }
+        cells[i].digit = Some(digit);
+        for run in &cells[i].runs {
+            used[*run] |= 1 << digit;
+        }
+        let is_filled = fill_rec(cells, used, rand, steps);
+        for run in &cells[i].runs {
+            used[*run] &= !(1 << digit);
+        }
+        if is_filled {
+            return true;
+        }
+        cells[i].digit = None;
+    }
+    false
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(symmetry: &str) -> Result<Self, Self::Err> {
+        match symmetry {
+            "none" => Ok(Symmetry::None),
+            "rotational" => Ok(Symmetry::Rotational),
+            "mirror" => Ok(Symmetry::Mirror),
+            _ => Err(format!(
+                "Unknown symmetry {:?}, expected none, rotational or mirror.",
+                symmetry
+            )),
+        }
+    }
+}
+
+impl Display for Symmetry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Symmetry::None => write!(f, "none"),
+            Symmetry::Rotational => write!(f, "rotational"),
+            Symmetry::Mirror => write!(f, "mirror"),
+        }
+    }
+}
@@ -1,15 +1,230 @@
-pub const LOGGING: bool = false;
+//! Diagnostics about what the solvers are doing. Every message has a level and
+//! a target, which is the module it comes from without the crate and
+//! `solvers` prefix, like `divide` or `earlier_anchor::simple_solver`. Which
+//! messages get written where is configured at runtime using `init`. Before
+//! that, nothing is logged.
+//!
+//! Messages can carry structured fields that are appended as `key=value`:
+//!
+//! ```ignore
+//! log!("Solving input.");
+//! log!(Trace; "Evaluating attempt {}", attempt);
+//! log!(Info, red = 3, blue = 4; "Combining solutions.");
+//! log!(depth = 2; "Splitting.");
+//! ```
+//!
+//! Messages without a level are `Debug`.
+
+use std::{
+    fmt::{self, Debug},
+    io::Write,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl Level {
+    /// The level for passing `-v` the given number of times.
+    pub fn from_verbosity(verbosity: u8) -> Self {
+        match verbosity {
+            0 => Level::Warn,
+            1 => Level::Info,
+            2 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("Unknown log level {:?}.", level)),
+        }
+    }
+}
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+/// Decides up to which level messages of a target are logged. `None` means
+/// that nothing is logged.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    default: Option<Level>,
+    targets: Vec<(String, Option<Level>)>,
+}
+impl Filter {
+    pub fn new(default: Option<Level>) -> Self {
+        Self {
+            default,
+            targets: vec![],
+        }
+    }
+
+    /// Sets the level of a target and all targets nested in it.
+    pub fn target(mut self, target: &str, level: Option<Level>) -> Self {
+        self.targets.push((target.to_string(), level));
+        self
+    }
+
+    /// Applies comma-separated directives like `debug,divide=trace,partition=off`.
+    /// A directive without a target sets the default level.
+    pub fn parse_directives(mut self, directives: &str) -> Result<Self, String> {
+        fn parse_level(level: &str) -> Result<Option<Level>, String> {
+            match level {
+                "off" => Ok(None),
+                level => level.parse().map(Some),
+            }
+        }
+        for directive in directives.split(',').filter(|it| !it.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => self = self.target(target, parse_level(level)?),
+                None => self.default = parse_level(directive)?,
+            }
+        }
+        Ok(self)
+    }
+
+    fn level_for(&self, target: &str) -> Option<Level> {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix
+                    || target.starts_with(prefix.as_str()) && target[prefix.len()..].starts_with("::")
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> Option<Level> {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .chain([self.default])
+            .max()
+            .flatten()
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    out: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+// Checked before looking at the logger, so disabled messages only cost an
+// atomic load.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+lazy_static! {
+    static ref LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+}
+
+/// Starts logging the messages that pass the filter to `out`, like
+/// `std::io::stderr()` or a file.
+pub fn init(filter: Filter, out: Box<dyn Write + Send>) {
+    let max_level = filter.max_level().map(|level| level as u8).unwrap_or(0);
+    *LOGGER.lock().unwrap() = Some(Logger {
+        filter,
+        out,
+        start: Instant::now(),
+    });
+    MAX_LEVEL.store(max_level, Ordering::Relaxed);
+}
+
+fn target(module_path: &str) -> &str {
+    let path = module_path.split_once("::").map_or("", |(_, path)| path);
+    path.strip_prefix("solvers::").unwrap_or(path)
+}
+
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+    match &*LOGGER.lock().unwrap() {
+        Some(logger) => logger.filter.level_for(target(module_path)) >= Some(level),
+        None => false,
+    }
+}
+
+pub fn write(
+    level: Level,
+    module_path: &str,
+    fields: &[(&str, &dyn Debug)],
+    message: fmt::Arguments,
+) {
+    let mut logger = LOGGER.lock().unwrap();
+    let logger = match &mut *logger {
+        Some(logger) => logger,
+        None => return,
+    };
+    let mut line = format!(
+        "{:>10.3}s {:<5} {}: {}",
+        logger.start.elapsed().as_secs_f64(),
+        level,
+        target(module_path),
+        message
+    );
+    for (key, value) in fields {
+        line.push_str(&format!(" {}={:?}", key, value));
+    }
+    // Logging is best effort, a full disk shouldn't abort solving.
+    writeln!(logger.out, "{}", line).ok();
+}
 
 #[macro_export]
 macro_rules! log {
-    () => {
-        if $crate::log::LOGGING {
-            std::println!()
+    ($level:ident, $($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Level::$level, std::module_path!()) {
+            $crate::log::write(
+                $crate::log::Level::$level,
+                std::module_path!(),
+                &[$((std::stringify!($key), &$value as &dyn std::fmt::Debug)),+],
+                std::format_args!($($arg)+),
+            )
         }
     };
-    ($($arg:tt)*) => {
-        if $crate::log::LOGGING {
-            std::println!($($arg)*)
+    ($level:ident ; $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Level::$level, std::module_path!()) {
+            $crate::log::write(
+                $crate::log::Level::$level,
+                std::module_path!(),
+                &[],
+                std::format_args!($($arg)+),
+            )
         }
     };
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        $crate::log!(Debug, $($key = $value),+ ; $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!(Debug; $($arg)+)
+    };
 }
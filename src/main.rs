@@ -4,26 +4,82 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod alloc_counter;
+mod batch;
+mod bench;
 mod board;
+mod canonical;
+mod difftest;
 mod game;
 mod generate;
+mod graph;
 mod import;
+mod layout;
 mod log;
+mod minimize;
+mod partition;
+mod rating;
+mod reference;
+mod search_tree;
 mod solvers;
+mod stats;
+mod suite;
 mod svg;
+mod uniqueness;
 
-use crate::{board::*, game::Input};
+use crate::{
+    board::*,
+    game::Input,
+    search_tree::SearchTree,
+    solvers::{CancellationToken, MemoryBudget, Monitor, Progress},
+};
 use import::ImportJsonBoard;
 use itertools::Itertools;
-use std::{fs, io::Write, path::PathBuf, time::Instant};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "kakuro", about = "A Kakuro tool.")]
+struct Options {
+    /// Log what the solvers are doing. Repeat for more details.
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+
+    /// Log levels per target, like `debug,divide=trace,partition=off`. Targets
+    /// are solvers or other modules.
+    #[structopt(long, global = true)]
+    log: Option<String>,
+
+    /// Write the log to this file instead of stderr.
+    #[structopt(long, parse(from_os_str), global = true)]
+    trace_file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: KakuroOptions,
+}
+
+#[derive(StructOpt, Debug)]
 enum KakuroOptions {
     /// Generates a new Kakuro with the given width and height. The fill
     /// indicates what percentage of the cells should be empty vs. walls. For
     /// example, a fill of 0.1 indicates that 10% of cells should be empty.
+    /// The file starts with a comment saying how to generate the same Kakuro
+    /// again.
+    ///
+    /// If --symmetry or --min-run is given, a layout of walls like the ones of
+    /// published Kakuros is built first: It is symmetric, has no runs longer
+    /// than 9 cells and all its cells are connected. Then, it's filled with
+    /// digits.
+    ///
+    /// If --difficulty is given, such Kakuros are generated, filled so that
+    /// they have a unique solution and rated until one has the difficulty.
     Generate {
         width: usize,
         height: usize,
@@ -31,6 +87,90 @@ enum KakuroOptions {
 
         #[structopt(parse(from_os_str))]
         out: PathBuf,
+
+        #[structopt(flatten)]
+        options: GenerateOptions,
+    },
+    /// Generates many different Kakuros with a unique solution in parallel,
+    /// like `generate` with a layout does. Kakuros that are rotations or
+    /// reflections of others are dropped. Writes them into the directory
+    /// together with an index.json containing their size, density, seed,
+    /// difficulty and number of solutions.
+    GenerateBatch {
+        width: usize,
+        height: usize,
+        fill: f64,
+
+        /// How many Kakuros to generate.
+        #[structopt(long)]
+        count: usize,
+
+        #[structopt(long, parse(from_os_str))]
+        out_dir: PathBuf,
+
+        /// The seed of the first candidate, the others get the following
+        /// seeds. Defaults to a random one.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// The symmetry of the layout: none, rotational or mirror.
+        #[structopt(long, default_value = "none")]
+        symmetry: layout::Symmetry,
+
+        /// The minimum length of runs in the layout.
+        #[structopt(long, default_value = "2")]
+        min_run: usize,
+
+        /// Only keep Kakuros of this difficulty: easy, medium, hard or expert.
+        #[structopt(long)]
+        difficulty: Option<rating::Difficulty>,
+
+        /// Give up on a candidate after this time. Candidates that get a
+        /// unique solution at all usually get it quickly, so trying more
+        /// candidates is faster than waiting for slow ones.
+        #[structopt(long, parse(try_from_str = parse_duration), default_value = "1s")]
+        timeout: Duration,
+
+        /// The number of threads. Defaults to the number of cores.
+        #[structopt(long)]
+        threads: Option<usize>,
+    },
+    /// Removes as many sums from a Kakuro with a unique solution as possible
+    /// while keeping its solution unique. Runs without a sum only require
+    /// different digits. Sums are tried in random order. Prints the Kakuro if
+    /// no output file is given.
+    MinimizeClues {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        out: Option<PathBuf>,
+
+        /// The seed for the order of the sums. Defaults to a random one.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Stop removing sums after this time.
+        #[structopt(long, parse(try_from_str = parse_duration), default_value = "1m")]
+        timeout: Duration,
+    },
+    /// Fills a template with digits so that it becomes a Kakuro with a unique
+    /// solution. A template is a Kakuro whose walls don't need sums, like a
+    /// shape drawn by hand. Prints the Kakuro if no output file is given.
+    FillTemplate {
+        #[structopt(parse(from_os_str))]
+        template: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        out: Option<PathBuf>,
+
+        /// The seed for the random generator. Defaults to a random one.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Give up after this time.
+        #[structopt(long, parse(try_from_str = parse_duration), default_value = "1m")]
+        timeout: Duration,
     },
     /// Imports a JSON Kakuro from kakuros.com, which you can get by looking at
     /// the source code. Compared to Kakuros generated using this tool, they are
@@ -45,21 +185,156 @@ enum KakuroOptions {
 
         #[structopt(parse(from_os_str))]
         file: PathBuf,
+
+        #[structopt(flatten)]
+        options: SolveOptions,
+    },
+    /// Checks that a solver finds exactly the reference solutions of a Kakuro.
+    /// Solution files ending in .json are in the format of kakuros.com, all
+    /// others list one solution per line the way `solve` prints them. With
+    /// --suite, checks all puzzles of the suite that have a solution file,
+    /// like the ones of the golden suite. Exits with 1 if the check fails or,
+    /// unless --allow-skips is given, if the solver timed out on a puzzle.
+    Verify {
+        solver: String,
+
+        #[structopt(parse(from_os_str), required_unless = "suite")]
+        puzzle: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str), required_unless = "suite")]
+        solution: Option<PathBuf>,
+
+        /// The suite to check, either the name of a manifest in the suites
+        /// directory, like golden, or the path to one.
+        #[structopt(long, conflicts_with_all = &["puzzle", "solution"])]
+        suite: Option<String>,
+
+        /// Don't fail if the solver gives up on some puzzles of the suite,
+        /// for slow solvers that can't solve all of them in time.
+        #[structopt(long, requires = "suite")]
+        allow_skips: bool,
+
+        #[structopt(flatten)]
+        options: SolveOptions,
+    },
+    /// Solves a Kakuro while recording the search tree and summarizes it: How
+    /// many nodes the search has at each depth, how much it branches and which
+    /// constraints fail most often.
+    ExplainSearch {
+        solver: String,
+
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(flatten)]
+        options: SolveOptions,
+    },
+    /// Counts the solutions of a Kakuro without constructing them.
+    Count {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
     },
     /// Benchmarks the given solver on a bunch of example Kakuros, or only the
     /// one given. Measures the runtime several times and prints information
     /// about the median and standard deviation.
     Bench {
-        solver: String,
+        #[structopt(required_unless = "all")]
+        solver: Option<String>,
 
         #[structopt(parse(from_os_str))]
         file: Option<PathBuf>,
 
+        /// Benchmark all solvers. Each solver runs in its own process for each
+        /// input, so timeouts and running out of memory don't stop the
+        /// benchmark. Without a timeout, solvers run for at most 30 minutes.
+        #[structopt(long, conflicts_with = "solver")]
+        all: bool,
+
+        #[structopt(flatten)]
+        bench: BenchOptions,
+
+        #[structopt(flatten)]
+        options: SolveOptions,
+    },
+    /// Compares two stored JSON benchmark results input by input and decides
+    /// whether the new one is faster using bootstrapped confidence intervals.
+    /// Exits with 1 if there are regressions.
+    BenchCompare {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// Only slowdowns bigger than this fraction are regressions.
+        #[structopt(long, default_value = "0.05")]
+        threshold: f64,
+    },
+    /// Regenerates the measurement table and raw measurement list of the README
+    /// from stored JSON benchmark results. Later results override earlier ones.
+    Report {
+        #[structopt(parse(from_os_str), required = true)]
+        results: Vec<PathBuf>,
+
+        /// Update the measurements in this README instead of printing them.
+        #[structopt(long, parse(from_os_str))]
+        readme: Option<PathBuf>,
+    },
+    /// Exports the graph of cells and runs of a Kakuro, placed at their
+    /// position on the board, and prints statistics about it.
+    Graph {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        /// The file to write the graph to. Files ending in .graphml are
+        /// written in the GraphML format, all others in the Graphviz format.
+        #[structopt(long, parse(from_os_str))]
+        out: Option<PathBuf>,
+
+        /// Color the cells by the red/blue split that the given divide solver
+        /// would choose first: earlier_anchor or partitioned.
         #[structopt(long)]
-        warm_up: bool,
+        split: Option<String>,
+    },
+    /// Solves random Kakuros with several solvers and checks that they all
+    /// find the same solutions. If they disagree, the Kakuro is shrunk to a
+    /// minimal one on which they still disagree, which is written to a file.
+    /// Exits with 1 if the solvers disagree.
+    Difftest {
+        /// How many Kakuros to solve.
+        #[structopt(long, default_value = "100")]
+        count: usize,
 
+        /// The seed for generating the Kakuros. Defaults to a random one,
+        /// which is printed so that failures can be reproduced.
         #[structopt(long)]
-        num_runs: Option<usize>,
+        seed: Option<u64>,
+
+        /// The maximum width and height of the Kakuros, not counting the
+        /// walls at the top and left.
+        #[structopt(long, default_value = "5")]
+        max_size: usize,
+
+        /// The solvers to compare, separated by commas. Defaults to all except
+        /// gradual and sum_table, which are known to find invalid solutions.
+        #[structopt(long, use_delimiter = true)]
+        solvers: Vec<String>,
+
+        /// Stop each solver after this time. Solvers that time out are not
+        /// compared.
+        #[structopt(long, parse(try_from_str = parse_duration), default_value = "1s")]
+        timeout: Duration,
+
+        /// Where to write the minimal Kakuro on which the solvers disagree.
+        #[structopt(long, parse(from_os_str), default_value = "counterexample.kakuro")]
+        out: PathBuf,
+    },
+    /// Prints the content hashes of Kakuros. Kakuros that only differ by
+    /// rotations, reflections or walls without sums at the borders have the
+    /// same hash. Also lists the files that contain the same Kakuro.
+    Hash {
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
     },
     /// Converts a Kakuro to an SVG.
     Svg {
@@ -71,56 +346,543 @@ enum KakuroOptions {
     },
 }
 
+#[derive(StructOpt, Debug)]
+struct BenchOptions {
+    /// The suite of puzzles to benchmark, either the name of a manifest in the
+    /// suites directory, like quick or stress, or the path to one. If a single
+    /// file is benchmarked, the suite is only used to check its solutions.
+    #[structopt(long, default_value = "default")]
+    suite: String,
+
+    /// Only benchmark the puzzles of the suite with this tag.
+    #[structopt(long)]
+    tag: Option<String>,
+
+    /// With --all, kill solvers using more than this many bytes, like 4G.
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    memory_limit: Option<usize>,
+
+    /// Solve each input for a few seconds before measuring it.
+    #[structopt(long)]
+    warm_up: bool,
+
+    /// Solve each input this many times. By default, inputs are solved until
+    /// the median is known precisely enough.
+    #[structopt(long)]
+    num_runs: Option<usize>,
+
+    /// Store the results in this file. Files ending in .csv are written as
+    /// CSV, all others as JSON.
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct GenerateOptions {
+    /// The seed for the random generator. Defaults to a random one.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// The symmetry of the layout: none, rotational or mirror.
+    #[structopt(long)]
+    symmetry: Option<layout::Symmetry>,
+
+    /// The minimum length of runs in the layout. Defaults to 2.
+    #[structopt(long)]
+    min_run: Option<usize>,
+
+    /// The difficulty: easy, medium, hard or expert. Hard and expert Kakuros
+    /// are rare, so finding one of 9x9 cells with a fill of 0.6 can take a few
+    /// minutes. Fills above 0.6 rarely work.
+    #[structopt(long)]
+    difficulty: Option<rating::Difficulty>,
+
+    /// Give up on finding a Kakuro of the difficulty after this time.
+    #[structopt(long, parse(try_from_str = parse_duration), default_value = "5m")]
+    timeout: Duration,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct SolveOptions {
+    /// The number of threads used by parallel solvers. Defaults to the number
+    /// of available cores.
+    #[structopt(long)]
+    threads: Option<usize>,
+
+    /// The approximate number of bytes that memory-bounded solvers may use,
+    /// like 512M or 4G. If they need more, solving fails. Only the divide
+    /// solvers from divide to earlier_anchor and partitioned are
+    /// memory-bounded.
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    max_memory: Option<usize>,
+
+    /// If a memory-bounded solver exceeds its budget, solve the Kakuro using
+    /// backtracking instead of failing.
+    #[structopt(long)]
+    fallback: bool,
+
+    /// Let the divide solvers that build quasi solutions split Kakuros with a
+    /// graph partitioner, which balances both parts and minimizes the number
+    /// of connecting constraints. The partitioned solver is earlier_anchor
+    /// with this flag.
+    #[structopt(long)]
+    partition: bool,
+
+    /// Stop solving after this time, like 500ms, 30s or 5m. Plain numbers are
+    /// seconds.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+
+    /// Show a live line on stderr with how far the solver got.
+    #[structopt(long)]
+    progress: bool,
+
+    /// Record the search tree into this file. Files ending in .dot are written
+    /// in the Graphviz format, all others as JSON.
+    #[structopt(long, parse(from_os_str))]
+    record_search: Option<PathBuf>,
+
+    /// Stop recording the search tree after this many nodes.
+    #[structopt(long, default_value = "100000")]
+    max_recorded_nodes: usize,
+}
+impl SolveOptions {
+    fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|it| it.get())
+                .unwrap_or(1)
+        })
+    }
+}
+
+fn parse_bytes(bytes: &str) -> Result<usize, String> {
+    let (number, factor) = match bytes.chars().last() {
+        Some('K') => (&bytes[..bytes.len() - 1], 1 << 10),
+        Some('M') => (&bytes[..bytes.len() - 1], 1 << 20),
+        Some('G') => (&bytes[..bytes.len() - 1], 1 << 30),
+        _ => (bytes, 1),
+    };
+    number
+        .parse::<usize>()
+        .map(|number| number * factor)
+        .map_err(|_| format!("Invalid number of bytes {:?}.", bytes))
+}
+
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, factor) = if let Some(number) = duration.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = duration.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = duration.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = duration.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (duration, 1.0)
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .map(|number| Duration::from_secs_f64(number * factor))
+        .ok_or_else(|| format!("Invalid duration {:?}.", duration))
+}
+
+fn init_logging(options: &Options) -> Result<(), String> {
+    let mut filter = log::Filter::new(Some(log::Level::from_verbosity(options.verbose)));
+    if let Some(directives) = &options.log {
+        filter = filter.parse_directives(directives)?;
+    }
+    let out: Box<dyn Write + Send> = match &options.trace_file {
+        Some(file) => Box::new(
+            fs::File::create(file).map_err(|err| format!("Couldn't create {:?}: {}", file, err))?,
+        ),
+        None => Box::new(std::io::stderr()),
+    };
+    log::init(filter, out);
+    Ok(())
+}
+
 fn main() {
-    match KakuroOptions::from_args() {
+    let options = Options::from_args();
+    if let Err(error) = init_logging(&options) {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    }
+    match options.command {
         KakuroOptions::Generate {
             width,
             height,
             fill,
             out,
-        } => generate(width, height, fill, out),
+            options,
+        } => generate(width, height, fill, out, &options),
+        KakuroOptions::GenerateBatch {
+            width,
+            height,
+            fill,
+            count,
+            out_dir,
+            seed,
+            symmetry,
+            min_run,
+            difficulty,
+            timeout,
+            threads,
+        } => generate_batch(
+            batch::Options {
+                layout: layout::Options {
+                    width,
+                    height,
+                    density: fill,
+                    symmetry,
+                    min_run,
+                },
+                difficulty,
+                timeout,
+                seed: seed.unwrap_or_else(|| rand::thread_rng().gen()),
+                count,
+                threads: threads.unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map(|it| it.get())
+                        .unwrap_or(1)
+                }),
+            },
+            &out_dir,
+        ),
+        KakuroOptions::FillTemplate {
+            template,
+            out,
+            seed,
+            timeout,
+        } => fill_template(&template, out.as_deref(), seed, timeout),
+        KakuroOptions::MinimizeClues {
+            file,
+            out,
+            seed,
+            timeout,
+        } => minimize_clues(&file, out.as_deref(), seed, timeout),
         KakuroOptions::Import { file } => import(file),
-        KakuroOptions::Solve { solver, file } => solve(solver, file),
+        KakuroOptions::Solve {
+            solver,
+            file,
+            options,
+        } => solve(solver, file, &options),
+        KakuroOptions::Verify {
+            solver,
+            puzzle,
+            solution,
+            suite,
+            allow_skips,
+            options,
+        } => match suite {
+            Some(suite) => verify_suite(&solver, &suite, allow_skips, &options),
+            None => verify(&solver, &puzzle.unwrap(), &solution.unwrap(), &options),
+        },
+        KakuroOptions::ExplainSearch {
+            solver,
+            file,
+            options,
+        } => explain_search(solver, file, &options),
+        KakuroOptions::Count { file } => count(file),
         KakuroOptions::Bench {
             solver,
             file,
-            warm_up,
-            num_runs,
-        } => benchmark(solver, file, warm_up, num_runs.unwrap_or(10)),
+            all,
+            bench,
+            options,
+        } => match solver {
+            Some(solver) => benchmark(solver, file, &bench, &options),
+            None => {
+                assert!(all);
+                benchmark_all(&bench, &options)
+            }
+        },
+        KakuroOptions::BenchCompare {
+            old,
+            new,
+            threshold,
+        } => bench_compare(&old, &new, threshold),
+        KakuroOptions::Report { results, readme } => report(&results, readme),
+        KakuroOptions::Graph { file, out, split } => graph(&file, out.as_deref(), split),
+        KakuroOptions::Difftest {
+            count,
+            seed,
+            max_size,
+            solvers,
+            timeout,
+            out,
+        } => difftest(count, seed, max_size, solvers, timeout, &out),
+        KakuroOptions::Hash { files } => hash(&files),
         KakuroOptions::Svg { file, out } => svg(&file, &out),
     }
 }
 
-fn generate(width: usize, height: usize, fill: f64, out: PathBuf) {
-    let board = generate::generate(
-        width,
-        height,
-        (width as f64 * height as f64 * fill) as usize,
+fn generate(width: usize, height: usize, fill: f64, out: PathBuf, options: &GenerateOptions) {
+    if width == 0 || height == 0 {
+        println!("The Kakuro must be at least 1x1.");
+        std::process::exit(2);
+    }
+    let GenerateOptions {
+        seed,
+        symmetry,
+        min_run,
+        difficulty,
+        timeout,
+    } = *options;
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut command = format!(
+        "kakuro generate {} {} {} --seed {}",
+        width, height, fill, seed
+    );
+    let mut comments = vec![];
+    let board = if symmetry.is_some() || min_run.is_some() || difficulty.is_some() {
+        let options = layout::Options {
+            width,
+            height,
+            density: fill,
+            symmetry: symmetry.unwrap_or(layout::Symmetry::None),
+            min_run: min_run.unwrap_or(2),
+        };
+        command += &format!(
+            " --symmetry {} --min-run {}",
+            options.symmetry, options.min_run
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let grid = match difficulty {
+            Some(difficulty) => {
+                command += &format!(" --difficulty {}", difficulty);
+                let mut rejections = generate::Rejections::default();
+                let result = generate::generate_with_difficulty(
+                    &options,
+                    difficulty,
+                    timeout,
+                    &mut rng,
+                    &mut rejections,
+                );
+                println!(
+                    "Rejected {} candidates: {} couldn't be made unique, {} were too easy and {} too hard.",
+                    rejections.not_unique + rejections.too_easy + rejections.too_hard,
+                    rejections.not_unique,
+                    rejections.too_easy,
+                    rejections.too_hard
+                );
+                let (grid, rating) = result.unwrap_or_else(|err| {
+                    println!("{}", err);
+                    std::process::exit(1);
+                });
+                comments.push(format!("# Difficulty: {}.", rating));
+                grid
+            }
+            None => options
+                .generate(&mut rng)
+                .and_then(|layout| layout::fill(&layout, &mut rng))
+                .unwrap_or_else(|err| {
+                    println!("{}", err);
+                    std::process::exit(2);
+                }),
+        };
+        generate::to_board(&grid)
+    } else {
+        generate::generate(
+            width,
+            height,
+            (width as f64 * height as f64 * fill) as usize,
+            seed,
+        )
+        .unwrap_or_else(|err| {
+            println!("{}", err);
+            std::process::exit(2);
+        })
+    };
+    let provenance = format!(
+        "# Generated by kakuro {} using `{}`.",
+        env!("CARGO_PKG_VERSION"),
+        command
+    );
+    comments.insert(0, provenance);
+    comments.push(format!("# Hash: {}", board.content_hash()));
+    fs::write(
+        out,
+        format!("{}\n{}", comments.join("\n"), board).as_bytes(),
+    )
+    .unwrap();
+}
+
+fn generate_batch(options: batch::Options, out_dir: &Path) {
+    let layout = &options.layout;
+    if layout.width == 0 || layout.height == 0 {
+        println!("The Kakuros must be at least 1x1.");
+        std::process::exit(2);
+    }
+    let mut command = format!(
+        "kakuro generate-batch {} {} {} --count {} --seed {} --symmetry {} --min-run {}",
+        layout.width,
+        layout.height,
+        layout.density,
+        options.count,
+        options.seed,
+        layout.symmetry,
+        layout.min_run
+    );
+    if let Some(difficulty) = options.difficulty {
+        command += &format!(" --difficulty {}", difficulty);
+    }
+    let start = Instant::now();
+    let result = batch::generate(&options);
+    let (kakuros, statistics) = result.unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(1);
+    });
+    println!(
+        "Generated {} Kakuros from {} candidates in {:?}: {} were duplicates and {} couldn't be generated.",
+        kakuros.len(),
+        statistics.candidates,
+        start.elapsed(),
+        statistics.duplicates,
+        statistics.failures
+    );
+    if options.difficulty.is_some() {
+        let rejections = &statistics.rejections;
+        println!(
+            "Rejected {} layouts: {} couldn't be made unique, {} were too easy and {} too hard.",
+            rejections.not_unique + rejections.too_easy + rejections.too_hard,
+            rejections.not_unique,
+            rejections.too_easy,
+            rejections.too_hard
+        );
+    }
+
+    fs::create_dir_all(out_dir)
+        .unwrap_or_else(|err| panic!("Couldn't create {:?}: {}", out_dir, err));
+    let digits = options.count.to_string().len();
+    let mut index = vec![];
+    for (i, kakuro) in kakuros.iter().enumerate() {
+        let file = format!("{:0width$}.kakuro", i + 1, width = digits);
+        let content = format!(
+            "# Generated by kakuro {} using `{}` from the seed {}.\n# Difficulty: {}.\n# Hash: {}\n{}",
+            env!("CARGO_PKG_VERSION"),
+            command,
+            kakuro.seed,
+            kakuro.rating,
+            kakuro.hash,
+            generate::to_board(&kakuro.grid)
+        );
+        let path = out_dir.join(&file);
+        fs::write(&path, content)
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", path, err));
+        index.push(batch::Entry::new(file, kakuro));
+    }
+    let path = out_dir.join("index.json");
+    fs::write(&path, serde_json::to_string_pretty(&index).unwrap())
+        .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", path, err));
+}
+
+fn fill_template(template: &PathBuf, out: Option<&Path>, seed: Option<u64>, timeout: Duration) {
+    let layout = layout::from_template(&read_kakuro(template)).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(2);
+    });
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let monitor = Monitor::new(CancellationToken::new(), Some(timeout));
+    let grid = uniqueness::fill_uniquely(&layout, &mut StdRng::seed_from_u64(seed), &monitor)
+        .unwrap_or_else(|err| {
+            println!("Couldn't fill the template: {}", err);
+            std::process::exit(1);
+        });
+    let board = generate::to_board(&grid);
+    let kakuro = format!(
+        "# Generated by kakuro {} using `kakuro fill-template {} --seed {}`.\n# Hash: {}\n{}",
+        env!("CARGO_PKG_VERSION"),
+        template.display(),
+        seed,
+        board.content_hash(),
+        board
+    );
+    match out {
+        Some(out) => fs::write(out, kakuro)
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", out, err)),
+        None => println!("{}", kakuro),
+    }
+}
+
+fn minimize_clues(file: &PathBuf, out: Option<&Path>, seed: Option<u64>, timeout: Duration) {
+    let mut board = read_kakuro(file);
+    let monitor = Monitor::new(CancellationToken::new(), Some(timeout));
+    match uniqueness::solutions(&board.to_input(), 2, &monitor) {
+        Ok(solutions) if solutions.len() == 1 => {}
+        Ok(_) => {
+            println!("The Kakuro doesn't have a unique solution.");
+            std::process::exit(2);
+        }
+        Err(err) => {
+            println!("Couldn't check that the solution is unique: {}", err);
+            std::process::exit(1);
+        }
+    }
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let sums = board.clue_positions().len();
+    let result = minimize::minimize(&mut board, &mut StdRng::seed_from_u64(seed), &monitor);
+    if let Err(err) = result {
+        eprintln!("Stopped removing sums: {}", err);
+    }
+    let kakuro = format!(
+        "# Generated by kakuro {} using `kakuro minimize-clues {} --seed {}`.\n# Removed {} of the {} sums.\n# Hash: {}\n{}",
+        env!("CARGO_PKG_VERSION"),
+        file.display(),
+        seed,
+        sums - board.clue_positions().len(),
+        sums,
+        board.content_hash(),
+        board
     );
-    fs::write(out, format!("{}", board).as_bytes()).unwrap();
+    match out {
+        Some(out) => fs::write(out, kakuro)
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", out, err)),
+        None => println!("{}", kakuro),
+    }
 }
 
 fn import(file: PathBuf) {
-    let input = fs::read(file.clone()).expect(&format!("Couldn't read file: {:?}", file));
     let input =
-        String::from_utf8(input).expect(&format!("The file {:?} contains non-UTF8 chars.", file));
-    let board = input.import_json().expect(&format!(
-        "The file {:?} doesn't contain a valid JSON Kakuro.",
-        file
-    ));
+        fs::read(&file).unwrap_or_else(|err| panic!("Couldn't read file {:?}: {}", file, err));
+    let input = String::from_utf8(input)
+        .unwrap_or_else(|_| panic!("The file {:?} contains non-UTF8 chars.", file));
+    let board = input.import_json().unwrap_or_else(|err| {
+        panic!(
+            "The file {:?} doesn't contain a valid JSON Kakuro: {}",
+            file, err
+        )
+    });
     let mut out = file;
     assert!(out.set_extension("kakuro"));
-    fs::write(out, format!("{}", board).as_bytes()).unwrap();
+    let kakuro = format!("# Hash: {}\n{}", board.content_hash(), board);
+    fs::write(out, kakuro.as_bytes()).unwrap();
 }
 
-fn solve(solver: String, file: PathBuf) {
+fn solve(solver: String, file: PathBuf, options: &SolveOptions) {
     let input = read_kakuro(&file).to_input();
     // println!("Input board abstracted to this:");
     // println!("{}", input);
     // println!();
 
     println!("Solving Kakuro.");
-    let solutions = raw_solve(&solver, &input);
+    let recording = options
+        .record_search
+        .as_ref()
+        .map(|_| Arc::new(Mutex::new(SearchTree::new(options.max_recorded_nodes))));
+    let solutions = raw_solve(&solver, &input, options, recording.clone());
+    if let (Some(file), Some(tree)) = (&options.record_search, recording) {
+        save_search_tree(file, &tree.lock().unwrap());
+    }
+    let solutions = match solutions {
+        Ok(solutions) => solutions,
+        Err(error) => {
+            println!("Couldn't solve the Kakuro: {}", error);
+            std::process::exit(1);
+        }
+    };
     println!("Done.");
     println!();
 
@@ -143,33 +905,402 @@ fn solve(solver: String, file: PathBuf) {
         println!();
     }
 }
-fn raw_solve(solver: &str, input: &Input) -> Vec<Vec<u8>> {
+fn verify(solver: &str, puzzle: &PathBuf, solution: &Path, options: &SolveOptions) {
+    let board = read_kakuro(puzzle);
+    let reference = reference::read(solution, &board).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(2);
+    });
+    let input = board.to_input();
+    let result = match raw_solve(solver, &input, options, None) {
+        Ok(solutions) => reference::verify(&input, &solutions, &reference),
+        Err(error) => Err(format!("Couldn't solve the Kakuro: {}", error)),
+    };
+    match result {
+        Ok(()) => println!(
+            "{} found the {} reference solutions.",
+            solver,
+            reference.len()
+        ),
+        Err(message) => {
+            println!("{}", message);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn verify_suite(solver: &str, suite: &str, allow_skips: bool, options: &SolveOptions) {
+    let suite = suite::Suite::find(suite).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(2);
+    });
+    println!("Suite {}: {}", suite.name, suite.description);
+    if suite
+        .puzzles
+        .iter()
+        .all(|puzzle| puzzle.reference.is_none())
+    {
+        println!("None of the puzzles has a solution file.");
+        std::process::exit(2);
+    }
+    let mut num_failed = 0;
+    let mut num_skipped = 0;
+    for puzzle in &suite.puzzles {
+        let reference = match &puzzle.reference {
+            Some(reference) => reference,
+            None => continue,
+        };
+        print!("{}: ", display_path(&puzzle.file));
+        std::io::stdout().flush().expect("Couldn't flush stdout.");
+        let input = read_kakuro(&puzzle.file).to_input();
+        let options = SolveOptions {
+            timeout: options.timeout.or(puzzle.timeout),
+            ..options.clone()
+        };
+        match raw_solve(solver, &input, &options, None) {
+            Ok(solutions) => match reference::verify(&input, &solutions, reference) {
+                Ok(()) => println!("ok"),
+                Err(message) => {
+                    println!("FAILED: {}", message);
+                    num_failed += 1;
+                }
+            },
+            // Solvers that are too slow for a puzzle aren't wrong, but they
+            // didn't show that they are right either.
+            Err(error) => {
+                println!("skipped: {}", error);
+                num_skipped += 1;
+            }
+        }
+    }
+    if num_failed > 0 {
+        println!("{} failed.", num_failed);
+    }
+    if num_skipped > 0 && !allow_skips {
+        println!(
+            "{} skipped. Use --allow-skips if the solver is too slow for them.",
+            num_skipped
+        );
+    }
+    if num_failed > 0 || (num_skipped > 0 && !allow_skips) {
+        std::process::exit(1);
+    }
+}
+
+fn raw_solve(
+    solver: &str,
+    input: &Input,
+    options: &SolveOptions,
+    recording: Option<Arc<Mutex<SearchTree>>>,
+) -> Result<Vec<Vec<u8>>, solvers::Error> {
+    let budget = match options.max_memory {
+        Some(_) if !MEMORY_BOUNDED_SOLVERS.contains(&solver) => {
+            eprintln!(
+                "The solver {} can't limit its memory. Only {} support --max-memory.",
+                solver,
+                MEMORY_BOUNDED_SOLVERS.join(", ")
+            );
+            std::process::exit(2);
+        }
+        Some(max_bytes) => MemoryBudget::new(max_bytes),
+        None => MemoryBudget::unlimited(),
+    };
+    if options.partition && !PARTITIONING_SOLVERS.contains(&solver) {
+        eprintln!(
+            "The solver {} can't use the partitioner. Only {} support --partition.",
+            solver,
+            PARTITIONING_SOLVERS.join(", ")
+        );
+        std::process::exit(2);
+    }
+    // The other solvers ignore runs without sums and would find solutions with
+    // the same digit twice in such a run or miss solutions.
+    if !input.all_different.is_empty() && !ALL_DIFFERENT_SOLVERS.contains(&solver) {
+        eprintln!(
+            "The solver {} ignores runs without a sum. Only {} support them.",
+            solver,
+            ALL_DIFFERENT_SOLVERS.join(", ")
+        );
+        std::process::exit(2);
+    }
+    let mut monitor = Monitor::new(CancellationToken::new(), options.timeout);
+    if let Some(tree) = recording {
+        monitor = monitor.with_recording(tree);
+    }
+    if options.progress {
+        monitor = monitor.with_progress(Duration::from_millis(200), Arc::new(print_progress));
+    }
+    let solutions = if MEMORY_BOUNDED_SOLVERS.contains(&solver) {
+        raw_solve_bounded(solver, input, options.partition, &budget, &monitor)
+    } else {
+        raw_solve_unbounded(solver, input, options.threads(), &monitor)
+    };
+    let solutions = match solutions {
+        Err(error @ solvers::Error::OutOfBudget { .. }) if options.fallback => {
+            eprintln!("{} Falling back to backtracking.", error);
+            solvers::no_alloc::solve(input, &monitor)
+        }
+        solutions => solutions,
+    };
+    if options.progress {
+        monitor.report();
+        eprintln!();
+    }
+    solutions
+}
+fn print_progress(progress: &Progress) {
+    let fraction = match progress.fraction {
+        Some(fraction) => format!("~{:.2} % explored", fraction * 100.0),
+        None => "unknown fraction explored".to_string(),
+    };
+    // Pad the line so that it overwrites longer previous lines.
+    eprint!(
+        "\r{:<100}",
+        format!(
+            "{} nodes, depth {}, {}, {} solutions, {:.1} s",
+            format_count(progress.nodes_visited),
+            progress.depth,
+            fraction,
+            progress.solutions_found,
+            progress.elapsed.as_secs_f64(),
+        )
+    );
+    std::io::stderr().flush().expect("Couldn't flush stderr.");
+}
+fn format_count(mut value: u64) -> String {
+    let units = ["", "K", "M", "G", "T"];
+    let mut magnitude = 0;
+    while value >= 10000 && magnitude < units.len() - 1 {
+        magnitude += 1;
+        value /= 1000;
+    }
+    format!("{}{}", value, units[magnitude])
+}
+// All solvers, in the order of the README.
+const SOLVERS: [&str; 23] = [
+    "naive",
+    "gradual",
+    "sum_reachable",
+    "prioritize",
+    "sum_reachable_no_set",
+    "only_check_changes",
+    "pass_empty_index",
+    "divide",
+    "connecting_cells",
+    "lazy",
+    "propagate_constraints",
+    "solution_in_rc",
+    "simpler_recursion_anchor",
+    "fxhashmap",
+    "better_vecs",
+    "iterative",
+    "array_vec",
+    "sum_table",
+    "earlier_anchor",
+    "partitioned",
+    "no_alloc",
+    "parallel",
+    "tree_decomposition",
+];
+
+// The solvers that are known to find invalid solutions, which difftest leaves
+// out by default.
+const WRONG_SOLVERS: [&str; 2] = ["gradual", "sum_table"];
+
+// The solvers that make sure that runs without a sum have different digits.
+const ALL_DIFFERENT_SOLVERS: [&str; 14] = [
+    "naive",
+    "divide",
+    "connecting_cells",
+    "lazy",
+    "propagate_constraints",
+    "solution_in_rc",
+    "simpler_recursion_anchor",
+    "fxhashmap",
+    "better_vecs",
+    "earlier_anchor",
+    "partitioned",
+    "no_alloc",
+    "parallel",
+    "tree_decomposition",
+];
+
+// The solvers that keep track of their memory and fail with
+// `Error::OutOfBudget` if they exceed the budget of --max-memory.
+const MEMORY_BOUNDED_SOLVERS: [&str; 10] = [
+    "divide",
+    "connecting_cells",
+    "lazy",
+    "propagate_constraints",
+    "solution_in_rc",
+    "simpler_recursion_anchor",
+    "fxhashmap",
+    "better_vecs",
+    "earlier_anchor",
+    "partitioned",
+];
+
+// The divide solvers that build quasi solutions and can split Kakuros with the
+// graph partitioner of --partition.
+const PARTITIONING_SOLVERS: [&str; 8] = [
+    "lazy",
+    "propagate_constraints",
+    "solution_in_rc",
+    "simpler_recursion_anchor",
+    "fxhashmap",
+    "better_vecs",
+    "earlier_anchor",
+    "partitioned",
+];
+
+fn raw_solve_bounded(
+    solver: &str,
+    input: &Input,
+    partition: bool,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Vec<Vec<u8>>, solvers::Error> {
+    match (solver, partition) {
+        ("divide", _) => solvers::divide::solve_within(input, budget, monitor),
+        ("connecting_cells", _) => solvers::connecting_cells::solve_within(input, budget, monitor),
+        ("lazy", false) => solvers::lazy::solve_within(input, budget, monitor),
+        ("lazy", true) => solvers::lazy::solve_partitioned_within(input, budget, monitor),
+        ("propagate_constraints", false) => {
+            solvers::propagate_constraints::solve_within(input, budget, monitor)
+        }
+        ("propagate_constraints", true) => {
+            solvers::propagate_constraints::solve_partitioned_within(input, budget, monitor)
+        }
+        ("solution_in_rc", false) => solvers::solution_in_rc::solve_within(input, budget, monitor),
+        ("solution_in_rc", true) => {
+            solvers::solution_in_rc::solve_partitioned_within(input, budget, monitor)
+        }
+        ("simpler_recursion_anchor", false) => {
+            solvers::simpler_recursion_anchor::solve_within(input, budget, monitor)
+        }
+        ("simpler_recursion_anchor", true) => {
+            solvers::simpler_recursion_anchor::solve_partitioned_within(input, budget, monitor)
+        }
+        ("fxhashmap", false) => solvers::fxhashmap::solve_within(input, budget, monitor),
+        ("fxhashmap", true) => solvers::fxhashmap::solve_partitioned_within(input, budget, monitor),
+        ("better_vecs", false) => solvers::better_vecs::solve_within(input, budget, monitor),
+        ("better_vecs", true) => {
+            solvers::better_vecs::solve_partitioned_within(input, budget, monitor)
+        }
+        ("earlier_anchor", false) => solvers::earlier_anchor::solve_within(input, budget, monitor),
+        ("earlier_anchor", true) | ("partitioned", _) => {
+            solvers::earlier_anchor::solve_partitioned_within(input, budget, monitor)
+        }
+        _ => panic!("The solver {} can't limit its memory.", solver),
+    }
+}
+
+fn raw_solve_unbounded(
+    solver: &str,
+    input: &Input,
+    threads: usize,
+    monitor: &Monitor,
+) -> Result<Vec<Vec<u8>>, solvers::Error> {
     match solver {
-        "naive" => solvers::naive::solve(&input),
-        "gradual" => solvers::gradual::solve(&input),
-        "sum_reachable" => solvers::sum_reachable::solve(&input),
-        "prioritize" => solvers::prioritize::solve(&input),
-        "sum_reachable_no_set" => solvers::sum_reachable_no_set::solve(&input),
-        "only_check_changes" => solvers::only_check_changes::solve(&input),
-        "pass_empty_index" => solvers::pass_empty_index::solve(&input),
-        "divide" => solvers::divide::solve(&input),
-        "connecting_cells" => solvers::connecting_cells::solve(&input),
-        "lazy" => solvers::lazy::solve(&input),
-        "propagate_constraints" => solvers::propagate_constraints::solve(&input),
-        "solution_in_rc" => solvers::solution_in_rc::solve(&input),
-        "simpler_recursion_anchor" => solvers::simpler_recursion_anchor::solve(&input),
-        "fxhashmap" => solvers::fxhashmap::solve(&input),
-        "better_vecs" => solvers::better_vecs::solve(&input),
-        "earlier_anchor" => solvers::earlier_anchor::solve(&input),
-        "iterative" => solvers::iterative::solve(&input),
-        "array_vec" => solvers::array_vec::solve(&input),
-        "sum_table" => solvers::sum_table::solve(&input),
-        "no_alloc" => solvers::no_alloc::solve(&input),
+        "naive" => solvers::naive::solve(input, monitor),
+        "gradual" => solvers::gradual::solve(input, monitor),
+        "sum_reachable" => solvers::sum_reachable::solve(input, monitor),
+        "prioritize" => solvers::prioritize::solve(input, monitor),
+        "sum_reachable_no_set" => solvers::sum_reachable_no_set::solve(input, monitor),
+        "only_check_changes" => solvers::only_check_changes::solve(input, monitor),
+        "pass_empty_index" => solvers::pass_empty_index::solve(input, monitor),
+        "iterative" => solvers::iterative::solve(input, monitor),
+        "array_vec" => solvers::array_vec::solve(input, monitor),
+        "sum_table" => solvers::sum_table::solve(input, monitor),
+        "no_alloc" => solvers::no_alloc::solve(input, monitor),
+        "parallel" => solvers::parallel::solve(input, threads, monitor),
+        "tree_decomposition" => solvers::tree_decomposition::solve(input, monitor),
         _ => panic!("Unknown solver {}.", solver),
     }
 }
 
-fn benchmark(solver: String, file: Option<PathBuf>, warm_up: bool, num_runs: usize) {
+fn save_search_tree(file: &Path, tree: &SearchTree) {
+    let content = match file.extension() {
+        Some(extension) if extension == "dot" => tree.to_dot(),
+        _ => tree.to_json(),
+    };
+    fs::write(file, content).unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", file, err));
+}
+
+fn explain_search(solver: String, file: PathBuf, options: &SolveOptions) {
+    let input = read_kakuro(&file).to_input();
+    let tree = Arc::new(Mutex::new(SearchTree::new(options.max_recorded_nodes)));
+    match raw_solve(&solver, &input, options, Some(tree.clone())) {
+        Ok(solutions) => println!("Found {} solutions.", solutions.len()),
+        Err(error) => println!("Solving stopped early: {}", error),
+    }
+    let tree = tree.lock().unwrap();
+    if let Some(file) = &options.record_search {
+        save_search_tree(file, &tree);
+    }
+    println!("Recorded {} nodes.", tree.nodes.len());
+    if tree.truncated {
+        println!(
+            "Stopped recording after {} nodes, so the numbers only cover the start of the search.",
+            tree.max_nodes
+        );
+    }
+    println!();
+
+    let depth_stats = tree.depth_stats();
+    println!("Depth     Nodes  Expanded  Branching    Failed  Solutions");
+    for (depth, stats) in depth_stats.iter().enumerate() {
+        if stats.nodes == 0 {
+            continue; // Parallel solvers don't record the top of the search.
+        }
+        let children = depth_stats.get(depth + 1).map_or(0, |next| next.nodes);
+        let branching = if stats.expanded > 0 {
+            format!("{:.2}", children as f64 / stats.expanded as f64)
+        } else {
+            "-".to_string()
+        };
+        println!(
+            "{:>5} {:>9} {:>9} {:>10} {:>9} {:>10}",
+            depth, stats.nodes, stats.expanded, branching, stats.failed, stats.solutions
+        );
+    }
+    println!();
+
+    let failing_constraints = tree.failing_constraints();
+    if failing_constraints.is_empty() {
+        return;
+    }
+    println!("Most often violated constraints:");
+    for (constraint, count) in failing_constraints.iter().take(10) {
+        match constraint {
+            Some(constraint) => println!(
+                "- {} times constraint {}: cells {:?} sum to {}",
+                count,
+                constraint,
+                input.constraints[*constraint].cells,
+                input.constraints[*constraint].sum
+            ),
+            None => println!("- {} times an unknown constraint", count),
+        }
+    }
+}
+
+fn count(file: PathBuf) {
+    let input = read_kakuro(&file).to_input();
+    let count = solvers::tree_decomposition::count(&input, &Monitor::unlimited()).unwrap();
+    println!("{}", count);
+}
+
+// Without a fixed number of runs, inputs are solved until the confidence
+// interval of the median is within this fraction of the median.
+const PRECISION: f64 = 0.01;
+const MIN_RUNS: usize = 5;
+const MAX_RUNS: usize = 100;
+// After this time, no new runs are started even if the median isn't precise.
+const MAX_ADAPTIVE_TIME: Duration = Duration::from_secs(30);
+const WARM_UP_TIME: Duration = Duration::from_secs(2);
+
+fn benchmark(solver: String, file: Option<PathBuf>, bench: &BenchOptions, options: &SolveOptions) {
     fn debug_warning() -> bool {
         println!("WARNING: You are running this binary in debug mode.");
         println!("Compile with `cargo build --release` to get a binary actually worth measuring.");
@@ -178,129 +1309,454 @@ fn benchmark(solver: String, file: Option<PathBuf>, warm_up: bool, num_runs: usi
     }
     debug_assert!(debug_warning());
 
-    const BENCHMARK_SUITE: [&'static str; 7] = [
-        "kakuros/mini.kakuro",
-        "kakuros/small.kakuro",
-        "kakuros/wikipedia.kakuro",
-        "kakuros/15x15.kakuro",
-        "kakuros/20x20.kakuro",
-        "kakuros/30x30.kakuro",
-        "kakuros/book.kakuro",
-    ];
-
-    let inputs = if let Some(file) = file {
-        vec![file]
-    } else {
-        BENCHMARK_SUITE
-            .iter()
-            .map(|path| PathBuf::from(path))
-            .collect_vec()
-    }
-    .into_iter()
-    .map(|file| (format!("{}", file.display()), read_kakuro(&file).to_input()))
-    .collect_vec();
-
-    // Warm up for 10 seconds.
-    if warm_up {
-        println!("Warming up.");
-        let warmup_start = chrono::Utc::now();
-        while chrono::Utc::now() < warmup_start + chrono::Duration::seconds(10) {
-            let input = &inputs[0];
-            raw_solve(&solver, &input.1);
+    let suite = load_suite(bench);
+    let inputs = match &file {
+        Some(file) => vec![(file.clone(), suite.puzzle(file))],
+        None => {
+            println!("Suite {}: {}", suite.name, suite.description);
+            println!();
+            suite
+                .puzzles
+                .iter()
+                .map(|puzzle| (puzzle.file.clone(), Some(puzzle)))
+                .collect()
         }
-        println!();
-    }
+    };
+
+    let mut measurements = vec![];
 
-    let mut results = vec![];
+    for (file, puzzle) in inputs {
+        let input = &read_kakuro(&file).to_input();
+        let file = display_path(&file);
+        println!("Input {}.", file);
+        let options = &SolveOptions {
+            timeout: options.timeout.or(puzzle.and_then(|it| it.timeout)),
+            ..options.clone()
+        };
+        if bench.warm_up {
+            print!("Warming up.");
+            std::io::stdout().flush().expect("Couldn't flush stdout.");
+            // Solve at least once, so that slow inputs are warmed up too.
+            let warm_up_start = Instant::now();
+            while raw_solve(&solver, input, options, None).is_ok()
+                && warm_up_start.elapsed() < WARM_UP_TIME
+            {}
+            println!();
+        }
 
-    for (input_string, input) in &inputs {
-        println!("Input {}.", input_string);
-        let mut durations = vec![];
-        for i in 0..num_runs {
+        let mut samples = vec![];
+        let mut allocations = vec![];
+        let mut outcome = bench::Outcome::Solved;
+        let start = Instant::now();
+        loop {
+            let is_done = match bench.num_runs {
+                Some(num_runs) => samples.len() >= num_runs,
+                None => {
+                    samples.len() >= MAX_RUNS
+                        || samples.len() >= MIN_RUNS
+                            && (start.elapsed() >= MAX_ADAPTIVE_TIME
+                                || stats::is_median_precise(
+                                    &samples.iter().map(|it| *it as f64).collect_vec(),
+                                    bench::CONFIDENCE,
+                                    PRECISION,
+                                ))
+                }
+            };
+            if is_done {
+                break;
+            }
             print!(
-                "Solving run {}/{} started at {}.",
-                i,
-                num_runs,
+                "Solving run {}{} started at {}.",
+                samples.len(),
+                bench
+                    .num_runs
+                    .map_or(String::new(), |num_runs| format!("/{}", num_runs)),
                 chrono::Local::now()
             );
             std::io::stdout().flush().expect("Couldn't flush stdout.");
             let before = Instant::now();
-            raw_solve(&solver, &input);
+            let (result, run_allocations) =
+                alloc_counter::measure(|| raw_solve(&solver, input, options, None));
             let after = Instant::now();
+            let solutions = match result {
+                Ok(solutions) => solutions,
+                Err(err) => {
+                    println!(" It failed: {}", err);
+                    outcome = match err {
+                        solvers::Error::OutOfBudget { .. } => bench::Outcome::OutOfMemory,
+                        solvers::Error::Timeout { .. } => bench::Outcome::Timeout,
+                        err => bench::Outcome::Failed {
+                            message: err.to_string(),
+                        },
+                    };
+                    break;
+                }
+            };
+            if let Some(Err(message)) = puzzle.map(|it| it.check(input, &solutions)) {
+                println!(" It's wrong: {}", message);
+                outcome = bench::Outcome::Wrong { message };
+                break;
+            }
             let runtime = after - before;
-            println!(" It took {} seconds.", runtime.as_secs_f64());
-            durations.push(after - before);
+            print!(" It took {} seconds.", runtime.as_secs_f64());
+            if let Some(run_allocations) = run_allocations {
+                print!(" {}.", run_allocations);
+                allocations.push(run_allocations);
+            }
+            println!();
+            samples.push(runtime.as_nanos() as u64);
+        }
+        let summary = bench::Summary::of(&samples).filter(|_| outcome == bench::Outcome::Solved);
+        let measurement = bench::Measurement {
+            solver: solver.clone(),
+            input: input_name(Path::new(&file)),
+            file,
+            outcome,
+            samples,
+            summary,
+            allocations,
+        };
+        if let Some(summary) = &measurement.summary {
+            println!("{}", summary.describe());
+            println!("{}", summary.describe_details());
+        }
+        if let Some(allocations) = measurement.median_allocations() {
+            println!("Median of {}", allocations);
         }
-        let durations = durations
-            .into_iter()
-            .map(|duration| duration.as_nanos())
-            .collect_vec();
-        let mean = mean(&durations).unwrap();
-        let deviation = std_deviation(&durations).unwrap() / mean;
-        let min = *durations.iter().min().unwrap() as f32;
-        let max = *durations.iter().max().unwrap() as f32;
-        println!(
-            "{} +- {:.2} %; {} – {}",
-            format_duration(mean),
-            deviation * 100.0,
-            format_duration(min),
-            format_duration(max),
-        );
         println!();
-
-        results.push((input_string, mean, deviation, min, max));
+        measurements.push(measurement);
     }
 
     println!("Summary:");
-    for (input, mean, deviation, min, max) in results {
-        println!(
-            "- {}: {} +- {:.2} %; {} - {}",
-            PathBuf::from(input)
-                .file_prefix()
-                .unwrap()
-                .to_str()
-                .unwrap(),
-            format_duration(mean),
-            deviation * 100.0,
-            format_duration(min),
-            format_duration(max)
-        );
+    for measurement in &measurements {
+        match &measurement.summary {
+            Some(summary) => print!("- {}: {}", measurement.input, summary.describe()),
+            None => print!("- {}: {}", measurement.input, measurement.outcome),
+        }
+        match measurement.median_allocations() {
+            Some(allocations) => println!("; {}", allocations),
+            None => println!(),
+        }
+    }
+
+    if let Some(output) = &bench.output {
+        let results = bench::BenchResults::new(measurements);
+        let content = match output.extension() {
+            Some(extension) if extension == "csv" => results.to_csv(),
+            _ => results.to_json(),
+        };
+        fs::write(output, content)
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", output, err));
     }
 }
-fn format_duration(mut value: f32) -> String {
-    let units = ["ns", "us", "ms", "s"];
-    let mut magnitude = 0;
-    while value > 1000.0 && magnitude < units.len() - 1 {
-        magnitude += 1;
-        value /= 1000.0;
+
+fn load_suite(bench: &BenchOptions) -> suite::Suite {
+    let suite = suite::Suite::find(&bench.suite).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(2);
+    });
+    match &bench.tag {
+        Some(tag) => suite.with_tag(tag),
+        None => suite,
     }
-    format!("{:.2} {}", value, units[magnitude])
 }
-fn mean(data: &[u128]) -> Option<f32> {
-    let sum = data.iter().sum::<u128>() as f32;
-    let count = data.len();
 
-    match count {
-        positive if positive > 0 => Some(sum / count as f32),
-        _ => None,
+// Paths in suites are absolute, but the ones below the current directory are
+// easier to read relative to it.
+fn display_path(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+// A short name of the input for the README, like `small`.
+fn input_name(file: &Path) -> String {
+    file.file_prefix().unwrap().to_str().unwrap().to_string()
+}
+
+fn benchmark_all(bench: &BenchOptions, options: &SolveOptions) {
+    let suite = load_suite(bench);
+    println!("Suite {}: {}", suite.name, suite.description);
+    println!();
+    let exe = std::env::current_exe().expect("Couldn't find the kakuro binary.");
+    let child_output =
+        std::env::temp_dir().join(format!("kakuro-bench-{}.json", std::process::id()));
+
+    let mut measurements = vec![];
+    for solver in SOLVERS {
+        for puzzle in &suite.puzzles {
+            let file = display_path(&puzzle.file);
+            print!("{} on {}:", solver, file);
+            std::io::stdout().flush().expect("Couldn't flush stdout.");
+
+            let timeout = options
+                .timeout
+                .or(puzzle.timeout)
+                .unwrap_or_else(|| Duration::from_secs(30 * 60));
+            let mut command = std::process::Command::new(&exe);
+            command
+                .args(["bench", solver])
+                .arg(&puzzle.file)
+                .arg("--suite")
+                .arg(&suite.path)
+                .arg("--timeout")
+                .arg(format!("{}ms", timeout.as_millis()))
+                .arg("--output")
+                .arg(&child_output);
+            if bench.warm_up {
+                command.arg("--warm-up");
+            }
+            if let Some(num_runs) = bench.num_runs {
+                command.arg("--num-runs").arg(num_runs.to_string());
+            }
+            if let Some(threads) = options.threads {
+                command.arg("--threads").arg(threads.to_string());
+            }
+            // Other solvers can't limit their memory.
+            if MEMORY_BOUNDED_SOLVERS.contains(&solver) {
+                if let Some(max_memory) = options.max_memory {
+                    command.arg("--max-memory").arg(max_memory.to_string());
+                }
+                if options.fallback {
+                    command.arg("--fallback");
+                }
+            }
+            if options.partition && PARTITIONING_SOLVERS.contains(&solver) {
+                command.arg("--partition");
+            }
+            // The child stops solving after the timeout on its own. Only kill
+            // it if it doesn't, for example while building huge solutions.
+            let time_limit = match bench.num_runs {
+                Some(num_runs) => timeout * num_runs as u32,
+                None => timeout * (MIN_RUNS as u32 + 1) + MAX_ADAPTIVE_TIME,
+            } + WARM_UP_TIME
+                + timeout
+                + Duration::from_secs(60);
+
+            let failed = |outcome| bench::Measurement {
+                solver: solver.to_string(),
+                input: input_name(&puzzle.file),
+                file: file.clone(),
+                outcome,
+                samples: vec![],
+                summary: None,
+                allocations: vec![],
+            };
+            let measurement =
+                match bench::run_limited(&mut command, Some(time_limit), bench.memory_limit) {
+                    bench::ChildExit::Success => fs::read_to_string(&child_output)
+                        .map_err(|err| err.to_string())
+                        .and_then(|json| bench::BenchResults::from_json(&json))
+                        .map(|mut results| results.measurements.remove(0))
+                        .unwrap_or_else(|message| failed(bench::Outcome::Failed { message })),
+                    bench::ChildExit::Timeout => failed(bench::Outcome::Timeout),
+                    bench::ChildExit::OutOfMemory => failed(bench::Outcome::OutOfMemory),
+                    bench::ChildExit::Failed(message) => failed(bench::Outcome::Failed { message }),
+                };
+            fs::remove_file(&child_output).ok();
+            match &measurement.summary {
+                Some(summary) => println!(" {}", summary.describe()),
+                None => println!(" {}", measurement.outcome),
+            }
+            measurements.push(measurement);
+        }
+    }
+
+    let results = bench::BenchResults::new(measurements);
+    println!();
+    print!("{}", bench::report(std::slice::from_ref(&results)));
+    if let Some(output) = &bench.output {
+        let content = match output.extension() {
+            Some(extension) if extension == "csv" => results.to_csv(),
+            _ => results.to_json(),
+        };
+        fs::write(output, content)
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", output, err));
     }
 }
-fn std_deviation(data: &[u128]) -> Option<f32> {
-    match (mean(data), data.len()) {
-        (Some(data_mean), count) if count > 0 => {
-            let variance = data
-                .iter()
-                .map(|value| {
-                    let diff = data_mean - (*value as f32);
 
-                    diff * diff
-                })
-                .sum::<f32>()
-                / count as f32;
+fn read_results(file: &Path) -> bench::BenchResults {
+    let json =
+        fs::read_to_string(file).unwrap_or_else(|err| panic!("Couldn't read {:?}: {}", file, err));
+    bench::BenchResults::from_json(&json).unwrap_or_else(|err| {
+        println!("{:?}: {}", file, err);
+        std::process::exit(2);
+    })
+}
+
+fn bench_compare(old: &Path, new: &Path, threshold: f64) {
+    let comparisons = bench::compare(&read_results(old), &read_results(new), threshold);
+    if comparisons.is_empty() {
+        println!("The results have no inputs in common.");
+        std::process::exit(2);
+    }
+    for comparison in &comparisons {
+        println!("{}", comparison);
+    }
+    let num_regressions = comparisons
+        .iter()
+        .filter(|it| it.verdict == bench::Verdict::Regression)
+        .count();
+    if num_regressions > 0 {
+        println!();
+        println!("{} regressions.", num_regressions);
+        std::process::exit(1);
+    }
+}
+
+fn report(results: &[PathBuf], readme: Option<PathBuf>) {
+    let results = results.iter().map(|file| read_results(file)).collect_vec();
+    match readme {
+        Some(readme) => {
+            let content = fs::read_to_string(&readme)
+                .unwrap_or_else(|err| panic!("Couldn't read {:?}: {}", readme, err));
+            match bench::update_readme(&content, &results) {
+                Ok(content) => fs::write(&readme, content)
+                    .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", readme, err)),
+                Err(error) => {
+                    println!("{}", error);
+                    std::process::exit(2);
+                }
+            }
+        }
+        None => print!("{}", bench::report(&results)),
+    }
+}
+
+fn graph(file: &PathBuf, out: Option<&Path>, split: Option<String>) {
+    let board = read_kakuro(file);
+    let input = board.to_input();
+    let mut graph = graph::Graph::new(&board, &input);
+    if let Some(solver) = split {
+        let partitioned = match solver.as_str() {
+            "earlier_anchor" => false,
+            "partitioned" => true,
+            _ => {
+                println!("Can't split like the {:?} solver.", solver);
+                std::process::exit(2);
+            }
+        };
+        match solvers::earlier_anchor::split_colors(&input, partitioned) {
+            Some(colors) => graph = graph.with_colors(colors),
+            None => println!("The {} solver doesn't split this Kakuro.", solver),
+        }
+    }
+    println!("{}", graph.stats());
+    if let Some(out) = out {
+        let content = match out.extension() {
+            Some(extension) if extension == "graphml" => graph.to_graphml(),
+            _ => graph.to_dot(),
+        };
+        fs::write(out, content)
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", out, err));
+    }
+}
+
+fn difftest(
+    count: usize,
+    seed: Option<u64>,
+    max_size: usize,
+    solvers: Vec<String>,
+    timeout: Duration,
+    out: &Path,
+) {
+    let solvers = if solvers.is_empty() {
+        SOLVERS
+            .iter()
+            .filter(|solver| !WRONG_SOLVERS.contains(solver))
+            .map(|solver| solver.to_string())
+            .collect_vec()
+    } else {
+        solvers
+    };
+    if let Some(unknown) = solvers.iter().find(|it| !SOLVERS.contains(&it.as_str())) {
+        println!("Unknown solver {}.", unknown);
+        std::process::exit(2);
+    }
+    if max_size == 0 {
+        println!("The maximum size must be at least 1.");
+        std::process::exit(2);
+    }
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    println!("Seed {}", seed);
+
+    let options = SolveOptions {
+        threads: None,
+        max_memory: None,
+        fallback: false,
+        partition: false,
+        timeout: Some(timeout),
+        progress: false,
+        record_search: None,
+        max_recorded_nodes: 0,
+    };
+    let solve = |solver: &str, input: &Input| raw_solve(solver, input, &options, None);
+    // Panicking solvers are reported as disagreeing, so don't print panics.
+    std::panic::set_hook(Box::new(|_| {}));
 
-            Some(variance.sqrt())
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut num_skipped = 0;
+    for i in 1..=count {
+        let grid = difftest::random_grid(max_size, &mut rng);
+        let comparison = difftest::compare(&generate::to_board(&grid), &solvers, &solve);
+        num_skipped += comparison.skipped.len();
+        if comparison.is_agreement() {
+            continue;
+        }
+        println!("The solvers disagree on Kakuro {}:", i);
+        print!("{}", comparison);
+
+        // Solvers that were skipped would probably time out again.
+        let solvers = comparison.compared_solvers();
+        let grid = difftest::shrink(grid, |board| {
+            !difftest::compare(board, &solvers, &solve).is_agreement()
+        });
+        let board = generate::to_board(&grid);
+        let comparison = difftest::compare(&board, &solvers, &solve);
+        println!("Shrunk to:\n{}\n{}", board, comparison);
+        let header =
+            format!(
+            "# The solvers disagree on this Kakuro, found with difftest --seed {} (Kakuro {}).\n{}",
+            seed,
+            i,
+            comparison.to_string().lines().map(|line| format!("# {}\n", line)).join("")
+        );
+        fs::write(out, format!("{}{}\n", header, board))
+            .unwrap_or_else(|err| panic!("Couldn't write to {:?}: {}", out, err));
+        println!("Written to {}.", out.display());
+        std::process::exit(1);
+    }
+    println!(
+        "The solvers agree on all {} Kakuros. {} times, a solver was skipped because it timed out or ran out of memory.",
+        count, num_skipped
+    );
+}
+
+fn hash(files: &[PathBuf]) {
+    let hashes = files
+        .iter()
+        .map(|file| read_kakuro(file).content_hash())
+        .collect_vec();
+    for (file, hash) in files.iter().zip(&hashes) {
+        println!("{}  {}", hash, file.display());
+    }
+    for (hash, same) in &files
+        .iter()
+        .zip(&hashes)
+        .sorted_by_key(|(_, hash)| *hash)
+        .group_by(|(_, hash)| *hash)
+    {
+        let same = same.map(|(file, _)| file.display()).collect_vec();
+        if same.len() > 1 {
+            println!(
+                "{} contain the same Kakuro {}.",
+                same.iter().join(", "),
+                hash
+            );
         }
-        _ => None,
     }
 }
 
@@ -0,0 +1,57 @@
+//! Removing sums from a Kakuro as long as its solution stays unique. Some
+//! publishers print Kakuros that only show part of the sums. Runs without a
+//! sum still require different digits.
+
+use crate::{
+    board::{Board, Cell, Value},
+    solvers::{Error, Monitor},
+    uniqueness,
+};
+use itertools::Itertools;
+use rand::{seq::SliceRandom, Rng};
+
+/// Tries to remove the sums of the Kakuro one after another in random order.
+/// A sum stays removed if the Kakuro still has a unique solution without it,
+/// so the Kakuro needs to have a unique solution to begin with. If the monitor
+/// stops the search, the sums removed until then stay removed.
+pub fn minimize(board: &mut Board, rand: &mut impl Rng, monitor: &Monitor) -> Result<(), Error> {
+    let input = board.to_input();
+    let cell_positions = board.cell_positions();
+    let mut clues = board
+        .clue_positions()
+        .into_iter()
+        .zip(&input.constraints)
+        .map(|((x, y), constraint)| {
+            // Horizontal runs start in the same row as their wall.
+            let is_horizontal = cell_positions[constraint.cells[0]].1 == y;
+            (x, y, is_horizontal)
+        })
+        .collect_vec();
+    clues.shuffle(rand);
+    for (x, y, horizontal) in clues {
+        let sum = sum_of(&mut board.cells[y][x], horizontal).take();
+        let is_unique =
+            uniqueness::solutions(&board.to_input(), 2, monitor).map(|it| it.len() == 1);
+        if is_unique != Ok(true) {
+            *sum_of(&mut board.cells[y][x], horizontal) = sum;
+        }
+        is_unique?;
+    }
+    Ok(())
+}
+
+fn sum_of(cell: &mut Cell, horizontal: bool) -> &mut Option<Value> {
+    match cell {
+        Cell::Wall {
+            horizontal_sum,
+            vertical_sum,
+        } => {
+            if horizontal {
+                horizontal_sum
+            } else {
+                vertical_sum
+            }
+        }
+        Cell::Empty => panic!("Only walls have sums."),
+    }
+}
@@ -0,0 +1,349 @@
+//! This module contains a partitioner that divides the cells of a game into
+//! two parts. Cells and constraints form a hypergraph: Each constraint is an
+//! edge connecting all of its cells. A good partition has parts of similar size
+//! and only few constraints that contain cells of both parts. Those are the
+//! connecting constraints that the divide solvers have to check when merging
+//! the solutions of the parts.
+
+use crate::log;
+use itertools::Itertools;
+use std::collections::VecDeque;
+
+// Parts may differ in size by this fraction of all cells. Allowing some
+// imbalance gives the refinement room to move cells around.
+const IMBALANCE: f64 = 0.3;
+const MAX_REFINEMENT_PASSES: usize = 10;
+
+/// Divides the cells into two parts that are each at least `min_part_size`
+/// big. Returns for each cell whether it belongs to the first part, or `None`
+/// if the cells can't be split into parts of that size.
+pub fn bisect(
+    num_cells: usize,
+    constraints: &[&[usize]],
+    min_part_size: usize,
+) -> Option<Vec<bool>> {
+    let min_part_size = min_part_size.max(1);
+    if num_cells < 2 * min_part_size {
+        return None;
+    }
+
+    let graph = Graph::new(num_cells, constraints);
+
+    let min_size = min_part_size.max((num_cells as f64 * (1.0 - IMBALANCE) / 2.0) as usize);
+    let max_size = num_cells - min_size;
+
+    // If the cells are already disconnected, no constraint needs to connect
+    // the parts.
+    if let Some(in_first) = graph.split_components(num_cells, min_part_size) {
+        return Some(in_first);
+    }
+
+    let mut in_first = graph.initial_partition(num_cells);
+    let mut num_passes = 0;
+    while num_passes < MAX_REFINEMENT_PASSES && graph.refine(&mut in_first, min_size, max_size) {
+        num_passes += 1;
+    }
+    let num_first = in_first.iter().filter(|it| **it).count();
+    if num_first < min_part_size || num_cells - num_first < min_part_size {
+        return None;
+    }
+    log!(
+        cells = num_cells,
+        passes = num_passes,
+        connections = num_connections(constraints, &in_first);
+        "Bisected cells."
+    );
+    Some(in_first)
+}
+
+/// Counts the constraints that contain cells of both parts.
+pub fn num_connections(constraints: &[&[usize]], in_first: &[bool]) -> usize {
+    constraints
+        .iter()
+        .filter(|cells| {
+            let num_first = cells.iter().filter(|cell| in_first[**cell]).count();
+            num_first > 0 && num_first < cells.len()
+        })
+        .count()
+}
+
+/// Groups the cells into sets that are connected by constraints.
+pub fn components(num_cells: usize, constraints: &[&[usize]]) -> Vec<Vec<usize>> {
+    Graph::new(num_cells, constraints).components(num_cells)
+}
+
+/// Returns the cells that disconnect their component if they are removed. A
+/// split that puts them between the parts only has to cut a few constraints.
+pub fn articulation_cells(num_cells: usize, constraints: &[&[usize]]) -> Vec<usize> {
+    Graph::new(num_cells, constraints).articulation_cells(num_cells)
+}
+
+struct Graph<'a> {
+    constraints: &'a [&'a [usize]],
+    constraints_of_cell: Vec<Vec<usize>>,
+}
+impl<'a> Graph<'a> {
+    fn new(num_cells: usize, constraints: &'a [&'a [usize]]) -> Self {
+        let mut constraints_of_cell = vec![vec![]; num_cells];
+        for (i, cells) in constraints.iter().enumerate() {
+            for cell in cells.iter() {
+                constraints_of_cell[*cell].push(i);
+            }
+        }
+        Self {
+            constraints,
+            constraints_of_cell,
+        }
+    }
+
+    fn neighbors(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        self.constraints_of_cell[cell]
+            .iter()
+            .flat_map(move |constraint| self.constraints[*constraint].iter().copied())
+    }
+
+    fn components(&self, num_cells: usize) -> Vec<Vec<usize>> {
+        let mut component_of_cell = vec![None; num_cells];
+        let mut components = vec![];
+        for start in 0..num_cells {
+            if component_of_cell[start].is_some() {
+                continue;
+            }
+            let mut component = vec![];
+            let mut dirty_queue = vec![start];
+            component_of_cell[start] = Some(components.len());
+            while let Some(current) = dirty_queue.pop() {
+                component.push(current);
+                for neighbor in self.neighbors(current) {
+                    if component_of_cell[neighbor].is_none() {
+                        component_of_cell[neighbor] = Some(components.len());
+                        dirty_queue.push(neighbor);
+                    }
+                }
+            }
+            components.push(component);
+        }
+        components
+    }
+
+    // Tarjan's algorithm with an explicit stack, because the depth first
+    // search can get as deep as the number of cells.
+    fn articulation_cells(&self, num_cells: usize) -> Vec<usize> {
+        let mut index: Vec<Option<usize>> = vec![None; num_cells];
+        let mut low = vec![0; num_cells];
+        let mut is_articulation = vec![false; num_cells];
+        let mut counter = 0;
+        for root in 0..num_cells {
+            if index[root].is_some() {
+                continue;
+            }
+            index[root] = Some(counter);
+            low[root] = counter;
+            counter += 1;
+            let mut root_children = 0;
+            // Each frame is a cell, its parent, its neighbors and how many of
+            // them were already visited.
+            let mut stack = vec![(root, None, self.neighbors(root).collect_vec(), 0)];
+            while let Some((cell, parent, neighbors, next)) = stack.last_mut() {
+                let (cell, parent) = (*cell, *parent);
+                if let Some(neighbor) = neighbors.get(*next).copied() {
+                    *next += 1;
+                    if neighbor == cell || Some(neighbor) == parent {
+                        continue;
+                    }
+                    match index[neighbor] {
+                        Some(neighbor_index) => low[cell] = low[cell].min(neighbor_index),
+                        None => {
+                            index[neighbor] = Some(counter);
+                            low[neighbor] = counter;
+                            counter += 1;
+                            if cell == root {
+                                root_children += 1;
+                            }
+                            let neighbors = self.neighbors(neighbor).collect_vec();
+                            stack.push((neighbor, Some(cell), neighbors, 0));
+                        }
+                    }
+                } else {
+                    stack.pop();
+                    if let Some(parent) = parent {
+                        low[parent] = low[parent].min(low[cell]);
+                        if parent != root && Some(low[cell]) >= index[parent] {
+                            is_articulation[parent] = true;
+                        }
+                    }
+                }
+            }
+            if root_children > 1 {
+                is_articulation[root] = true;
+            }
+        }
+        (0..num_cells)
+            .filter(|cell| is_articulation[*cell])
+            .collect()
+    }
+
+    /// Distributes whole components onto both parts, always adding the next
+    /// biggest component to the smaller part.
+    fn split_components(&self, num_cells: usize, min_part_size: usize) -> Option<Vec<bool>> {
+        let components = self.components(num_cells);
+        if components.len() < 2 {
+            return None;
+        }
+        let mut in_first = vec![false; num_cells];
+        let mut num_first = 0;
+        let mut num_second = 0;
+        for component in components
+            .iter()
+            .sorted_by_key(|it| std::cmp::Reverse(it.len()))
+        {
+            if num_first <= num_second {
+                num_first += component.len();
+                for cell in component {
+                    in_first[*cell] = true;
+                }
+            } else {
+                num_second += component.len();
+            }
+        }
+        if num_first < min_part_size || num_second < min_part_size {
+            None
+        } else {
+            Some(in_first)
+        }
+    }
+
+    /// All cells in breadth-first order, starting at the given cell. Once the
+    /// component of the cell is used up, the search continues with the first
+    /// cell that wasn't visited yet.
+    fn breadth_first(&self, num_cells: usize, start: usize) -> Vec<usize> {
+        let mut visited = vec![false; num_cells];
+        let mut order = vec![];
+        let mut queue = VecDeque::new();
+        for start in std::iter::once(start).chain(0..num_cells) {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            queue.push_back(start);
+            while let Some(current) = queue.pop_front() {
+                order.push(current);
+                for neighbor in self.neighbors(current) {
+                    if !visited[neighbor] {
+                        visited[neighbor] = true;
+                        queue.push_back(neighbor);
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Grows the first part from a cell at the edge of the graph until it
+    /// contains half of the cells. This gives a compact first part with a
+    /// short border. If the component of that cell is too small, the part
+    /// grows into other components.
+    fn initial_partition(&self, num_cells: usize) -> Vec<bool> {
+        // The last cell visited by a breadth-first search is far away from the
+        // start, so it's a good approximation of a peripheral cell.
+        let start = *self.breadth_first(num_cells, 0).last().unwrap();
+        let mut in_first = vec![false; num_cells];
+        for cell in self
+            .breadth_first(num_cells, start)
+            .into_iter()
+            .take(num_cells / 2)
+        {
+            in_first[cell] = true;
+        }
+        in_first
+    }
+
+    /// One pass of Fiduccia-Mattheyses refinement: Moves every cell once,
+    /// always choosing the move that reduces the number of connecting
+    /// constraints the most while keeping the parts balanced. Then rolls back
+    /// to the best partition seen during the pass. Returns whether the
+    /// partition improved.
+    fn refine(&self, in_first: &mut [bool], min_size: usize, max_size: usize) -> bool {
+        let num_cells = in_first.len();
+        let mut num_first_in_constraint = self
+            .constraints
+            .iter()
+            .map(|cells| cells.iter().filter(|cell| in_first[**cell]).count())
+            .collect_vec();
+        let mut num_first = in_first.iter().filter(|it| **it).count();
+        let mut locked = vec![false; num_cells];
+
+        let initial_connections = num_connections(self.constraints, in_first);
+        let mut connections = initial_connections;
+        let mut best_connections = initial_connections;
+        let mut moves = vec![];
+        let mut num_best_moves = 0;
+
+        loop {
+            let best_move = (0..num_cells)
+                .filter(|cell| !locked[*cell])
+                .filter(|cell| {
+                    if in_first[*cell] {
+                        num_first > min_size
+                    } else {
+                        num_first < max_size
+                    }
+                })
+                .max_by_key(|cell| self.gain(*cell, in_first, &num_first_in_constraint));
+            let cell = match best_move {
+                Some(cell) => cell,
+                None => break,
+            };
+
+            let gain = self.gain(cell, in_first, &num_first_in_constraint);
+            connections = (connections as isize - gain) as usize;
+            for constraint in &self.constraints_of_cell[cell] {
+                if in_first[cell] {
+                    num_first_in_constraint[*constraint] -= 1;
+                } else {
+                    num_first_in_constraint[*constraint] += 1;
+                }
+            }
+            if in_first[cell] {
+                num_first -= 1;
+            } else {
+                num_first += 1;
+            }
+            in_first[cell] = !in_first[cell];
+            locked[cell] = true;
+            moves.push(cell);
+
+            if connections < best_connections {
+                best_connections = connections;
+                num_best_moves = moves.len();
+            }
+        }
+
+        for cell in moves.into_iter().skip(num_best_moves) {
+            in_first[cell] = !in_first[cell];
+        }
+        best_connections < initial_connections
+    }
+
+    /// By how much the number of connecting constraints decreases if the cell
+    /// is moved to the other part.
+    fn gain(&self, cell: usize, in_first: &[bool], num_first_in_constraint: &[usize]) -> isize {
+        let mut gain = 0;
+        for constraint in &self.constraints_of_cell[cell] {
+            let len = self.constraints[*constraint].len();
+            let num_first = num_first_in_constraint[*constraint];
+            let (num_own, num_other) = if in_first[cell] {
+                (num_first, len - num_first)
+            } else {
+                (len - num_first, num_first)
+            };
+            if num_own == 1 && num_other > 0 {
+                gain += 1; // The constraint no longer connects both parts.
+            }
+            if num_other == 0 && num_own > 1 {
+                gain -= 1; // The constraint starts connecting both parts.
+            }
+        }
+        gain
+    }
+}
@@ -0,0 +1,131 @@
+//! Rating how difficult a Kakuro is for humans. Humans rarely guess. Instead,
+//! they look at which combinations of digits fit into a run and what that
+//! means for the crossing runs, which is what the `Propagator` does. So the
+//! rating counts how often a search that propagates the constraints after each
+//! step has to guess to find the solution and to make sure there's no other
+//! one.
+
+use crate::{
+    game::Input,
+    solvers::{Error, Monitor},
+    uniqueness::{Propagator, ALL_DIGITS},
+};
+use serde::Serialize;
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    /// Can be solved without guessing.
+    Easy,
+    /// Needs at most 5 guesses.
+    Medium,
+    /// Needs at most 20 guesses.
+    Hard,
+    Expert,
+}
+
+impl Difficulty {
+    fn of(guesses: u64) -> Self {
+        match guesses {
+            0 => Difficulty::Easy,
+            1..=5 => Difficulty::Medium,
+            6..=20 => Difficulty::Hard,
+            _ => Difficulty::Expert,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rating {
+    /// How many times the search had to guess a digit.
+    pub guesses: u64,
+    pub difficulty: Difficulty,
+}
+
+/// Rates the Kakuro. Returns `None` if it doesn't have a unique solution.
+pub fn rate(input: &Input, monitor: &Monitor) -> Result<Option<Rating>, Error> {
+    let propagator = Propagator::new(input);
+    let mut candidates = vec![ALL_DIGITS; input.num_cells];
+    let mut guesses = 0;
+    let solutions = search(&propagator, &mut candidates, &mut guesses, monitor)?;
+    Ok((solutions == 1).then(|| Rating {
+        guesses,
+        difficulty: Difficulty::of(guesses),
+    }))
+}
+
+/// Returns how many solutions there are, but stops counting at 2.
+fn search(
+    propagator: &Propagator,
+    candidates: &mut [u16],
+    guesses: &mut u64,
+    monitor: &Monitor,
+) -> Result<usize, Error> {
+    monitor.visit()?;
+    propagator.propagate(candidates);
+    if candidates.contains(&0) {
+        return Ok(0);
+    }
+    let cell = match (0..candidates.len())
+        .filter(|cell| candidates[*cell].count_ones() > 1)
+        .min_by_key(|cell| candidates[*cell].count_ones())
+    {
+        Some(cell) => cell,
+        None => {
+            monitor.found_solution();
+            return Ok(1);
+        }
+    };
+    let mut solutions = 0;
+    for digit in 1..=9 {
+        if candidates[cell] & (1 << digit) == 0 {
+            continue;
+        }
+        *guesses += 1;
+        let mut guessed = candidates.to_vec();
+        guessed[cell] = 1 << digit;
+        solutions += search(propagator, &mut guessed, guesses, monitor)?;
+        if solutions >= 2 {
+            break;
+        }
+    }
+    Ok(solutions)
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(difficulty: &str) -> Result<Self, Self::Err> {
+        match difficulty {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            "expert" => Ok(Difficulty::Expert),
+            _ => Err(format!(
+                "Unknown difficulty {:?}, expected easy, medium, hard or expert.",
+                difficulty
+            )),
+        }
+    }
+}
+
+impl Display for Difficulty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Medium => write!(f, "medium"),
+            Difficulty::Hard => write!(f, "hard"),
+            Difficulty::Expert => write!(f, "expert"),
+        }
+    }
+}
+
+impl Display for Rating {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} guesses)", self.difficulty, self.guesses)
+    }
+}
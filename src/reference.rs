@@ -0,0 +1,85 @@
+//! Reference solutions that solvers can be verified against. They are stored
+//! either in the JSON format of kakuros.com (files ending in .json) or in our
+//! own format, which lists one solution per line in the way `kakuro solve`
+//! prints them: the digits of the cells in the order in which
+//! `Board::to_input` numbers them. Lines starting with `#` are comments.
+//!
+//! ```text
+//! # The solutions of small.kakuro.
+//! 1213
+//! 3231
+//! ```
+
+use crate::{
+    board::Board,
+    game::{Input, Output, Solution, Value},
+    import::ImportJsonSolution,
+};
+use itertools::Itertools;
+use std::{collections::BTreeSet, fs, path::Path};
+
+/// Reads the reference solutions of the board from the file.
+pub fn read(file: &Path, board: &Board) -> Result<BTreeSet<Solution>, String> {
+    let content =
+        fs::read_to_string(file).map_err(|err| format!("Couldn't read {:?}: {}", file, err))?;
+    let solutions = match file.extension() {
+        Some(extension) if extension == "json" => {
+            BTreeSet::from([content.import_json_solution(board)?])
+        }
+        _ => parse(&content, board.cell_positions().len())?,
+    };
+    Ok(solutions)
+}
+
+fn parse(content: &str, num_cells: usize) -> Result<BTreeSet<Solution>, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let solution = line
+                .chars()
+                .map(|char| match char.to_digit(10) {
+                    Some(digit @ 1..=9) => Ok(digit as Value),
+                    _ => Err(format!("Invalid digit {:?} in solution {:?}.", char, line)),
+                })
+                .collect::<Result<Solution, String>>()?;
+            if solution.len() != num_cells {
+                return Err(format!(
+                    "The solution {:?} has {} digits, but the Kakuro has {} cells.",
+                    line,
+                    solution.len(),
+                    num_cells
+                ));
+            }
+            Ok(solution)
+        })
+        .collect()
+}
+
+/// Checks that the solutions found by a solver are valid and exactly the
+/// reference solutions.
+pub fn verify(
+    input: &Input,
+    output: &Output,
+    reference: &BTreeSet<Solution>,
+) -> Result<(), String> {
+    if let Some(invalid) = output.iter().find(|it| !input.is_solution(it)) {
+        return Err(format!(
+            "Found an invalid solution {}.",
+            invalid.iter().join("")
+        ));
+    }
+    let found = output.iter().cloned().collect::<BTreeSet<_>>();
+    let missing = reference.difference(&found).count();
+    let unexpected = found.difference(reference).count();
+    if missing > 0 || unexpected > 0 {
+        return Err(format!(
+            "Missed {} of the {} reference solutions and found {} other ones.",
+            missing,
+            reference.len(),
+            unexpected
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! This module contains a recording of the search tree of a solver. For
+//! backtracking solvers, each node is the assignment of a digit to a cell,
+//! which may have violated a constraint or completed a solution. For divide
+//! solvers, each node is a split into a red and a blue part, whose children
+//! are the splits of the parts, or a leaf that was solved directly.
+//!
+//! Search trees get huge quickly, so the recording stops after a maximum
+//! number of nodes.
+
+use crate::game::Value;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchTree {
+    pub nodes: Vec<Node>,
+    pub max_nodes: usize,
+    /// Whether nodes were dropped because the tree reached its maximum size.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    pub parent: Option<usize>,
+    pub depth: usize,
+    #[serde(flatten)]
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NodeKind {
+    Assign {
+        cell: usize,
+        digit: Value,
+        /// Whether the assignment made the Kakuro invalid.
+        failed: bool,
+        /// The constraint that was violated, if the solver knows it.
+        violated: Option<usize>,
+        solution: bool,
+    },
+    Split {
+        red_cells: usize,
+        blue_cells: usize,
+        connections: usize,
+        /// How many combinations of red and blue solutions were checked.
+        candidates: Option<usize>,
+        /// How many of them fulfilled the connecting constraints.
+        merged: Option<usize>,
+    },
+    Leaf {
+        cells: usize,
+        solutions: usize,
+    },
+}
+
+/// Statistics about all nodes at one depth.
+#[derive(Debug, Clone, Default)]
+pub struct DepthStats {
+    pub nodes: usize,
+    /// Nodes that have children.
+    pub expanded: usize,
+    pub failed: usize,
+    pub solutions: usize,
+}
+
+impl SearchTree {
+    pub fn new(max_nodes: usize) -> Self {
+        Self {
+            nodes: vec![],
+            max_nodes,
+            truncated: false,
+        }
+    }
+
+    /// Adds a node and returns its index, or `None` if the tree is full.
+    pub fn add(&mut self, parent: Option<usize>, depth: usize, kind: NodeKind) -> Option<usize> {
+        if self.nodes.len() >= self.max_nodes {
+            self.truncated = true;
+            return None;
+        }
+        self.nodes.push(Node {
+            parent,
+            depth,
+            kind,
+        });
+        Some(self.nodes.len() - 1)
+    }
+
+    pub fn depth_stats(&self) -> Vec<DepthStats> {
+        let mut has_children = vec![false; self.nodes.len()];
+        for node in &self.nodes {
+            if let Some(parent) = node.parent {
+                has_children[parent] = true;
+            }
+        }
+        let max_depth = self.nodes.iter().map(|node| node.depth).max();
+        let mut stats = vec![DepthStats::default(); max_depth.map_or(0, |depth| depth + 1)];
+        for (node, has_children) in self.nodes.iter().zip(has_children) {
+            let stats = &mut stats[node.depth];
+            stats.nodes += 1;
+            if has_children {
+                stats.expanded += 1;
+            }
+            match node.kind {
+                NodeKind::Assign { failed: true, .. } => stats.failed += 1,
+                NodeKind::Assign { solution: true, .. } => stats.solutions += 1,
+                NodeKind::Leaf { solutions, .. } => stats.solutions += solutions,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// How often each constraint was violated, most often first. Failures
+    /// where the solver didn't know the constraint are counted as `None`.
+    pub fn failing_constraints(&self) -> Vec<(Option<usize>, usize)> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node.kind {
+                NodeKind::Assign {
+                    failed: true,
+                    violated,
+                    ..
+                } => Some(violated),
+                _ => None,
+            })
+            .counts()
+            .into_iter()
+            .sorted_by_key(|(constraint, count)| (std::cmp::Reverse(*count), *constraint))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph search {{").unwrap();
+        writeln!(dot, "  node [fontname=\"monospace\"];").unwrap();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let (label, shape, color) = match &node.kind {
+                NodeKind::Assign {
+                    cell,
+                    digit,
+                    failed,
+                    violated,
+                    solution,
+                } => {
+                    let label = format!("{} = {}", cell, digit);
+                    match (*failed, violated, *solution) {
+                        (true, Some(constraint), _) => (
+                            format!("{}\\nviolates {}", label, constraint),
+                            "ellipse",
+                            "red",
+                        ),
+                        (true, None, _) => (label, "ellipse", "red"),
+                        (false, _, true) => (label, "ellipse", "green"),
+                        (false, _, false) => (label, "ellipse", "black"),
+                    }
+                }
+                NodeKind::Split {
+                    red_cells,
+                    blue_cells,
+                    connections,
+                    candidates,
+                    merged,
+                } => {
+                    let mut label = format!(
+                        "split {} / {}\\n{} connections",
+                        red_cells, blue_cells, connections
+                    );
+                    if let (Some(candidates), Some(merged)) = (candidates, merged) {
+                        write!(label, "\\nmerged {} of {}", merged, candidates).unwrap();
+                    }
+                    (label, "box", "blue")
+                }
+                NodeKind::Leaf { cells, solutions } => (
+                    format!("{} cells\\n{} solutions", cells, solutions),
+                    "box",
+                    "black",
+                ),
+            };
+            writeln!(
+                dot,
+                "  n{} [label=\"{}\", shape={}, color={}];",
+                i, label, shape, color
+            )
+            .unwrap();
+            if let Some(parent) = node.parent {
+                writeln!(dot, "  n{} -> n{};", parent, i).unwrap();
+            }
+        }
+        if self.truncated {
+            writeln!(
+                dot,
+                "  truncated [label=\"Stopped recording after {} nodes.\", shape=note];",
+                self.max_nodes
+            )
+            .unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
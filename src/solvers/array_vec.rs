@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
     log,
@@ -36,6 +37,7 @@ impl ConstraintExt for Constraint {
             unused_digits[i] = !seen[i];
         }
         log!(
+            Trace;
             "Checking if any combination of {:?} with length {} plus existing sum {} yields total sum {}.",
             unused_digits,
             self.cells.len() - digits.len(),
@@ -50,7 +52,7 @@ impl ConstraintExt for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt = vec![None; input.num_cells];
     let mut solutions = vec![];
     let mut affected_constraints = HashMap::new();
@@ -59,8 +61,14 @@ pub fn solve(input: &Input) -> Output {
             affected_constraints.entry(*cell).or_insert(vec![]).push(i);
         }
     }
-    solve_rec(input, &affected_constraints, &mut attempt, &mut solutions);
-    solutions
+    solve_rec(
+        input,
+        &affected_constraints,
+        &mut attempt,
+        &mut solutions,
+        monitor,
+    )?;
+    Ok(solutions)
 }
 
 fn solve_rec(
@@ -68,8 +76,11 @@ fn solve_rec(
     affected_constraints: &HashMap<usize, Vec<usize>>,
     attempt: &mut Game,
     solutions: &mut Vec<Solution>,
-) {
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
     log!(
+        Trace;
         "Evaluating attempt {}",
         attempt
             .iter()
@@ -85,19 +96,23 @@ fn solve_rec(
     let first_empty_cell_index = attempt.iter().position(|it| it.is_none());
     if let Some(index) = first_empty_cell_index {
         'candidates: for i in 1..=9 {
+            monitor.assign(index, index, i);
             attempt[index] = Some(i);
             for constraint_index in &affected_constraints[&index] {
                 let constraint = &input.constraints[*constraint_index];
                 if !constraint.is_satisfied_by(attempt) {
+                    monitor.fail(Some(*constraint_index));
                     continue 'candidates;
                 }
             }
-            solve_rec(input, affected_constraints, attempt, solutions);
+            solve_rec(input, affected_constraints, attempt, solutions, monitor)?;
         }
         attempt[index] = None;
     } else {
         solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
 
 // A lookup table where you can look up the total number of digits as well as
@@ -1,6 +1,7 @@
+use super::{Error, MemoryBudget, Monitor};
 use crate::{
     game::{self, Output, Solution, Value},
-    log,
+    log, partition,
 };
 use arrayvec::ArrayVec;
 use itertools::Itertools;
@@ -8,6 +9,7 @@ use rustc_hash::FxHashMap;
 use std::{
     cmp::{max, min},
     fmt::{self, Display},
+    mem::{size_of, size_of_val},
     rc::Rc,
 };
 
@@ -105,64 +107,97 @@ fn split(num_cells: usize, constraints: &[Constraint]) -> Option<SplitInput> {
                 continue;
             }
 
-            // A vector that maps cell indizes from the original input to the
-            // cell indizes in the smaller parts.
-            let index_mapping = {
-                let mut red_counter = 0;
-                let mut blue_counter = 0;
-                let mut mapping: Vec<usize> = Default::default();
-                for i in 0..num_cells {
-                    match colors[i] {
-                        Color::Red => {
-                            mapping.push(red_counter);
-                            red_counter += 1;
-                        }
-                        Color::Blue => {
-                            mapping.push(blue_counter);
-                            blue_counter += 1;
-                        }
-                    }
-                }
-                mapping
-            };
+            return Some(split_by_colors(
+                colors,
+                &connecting_constraints,
+                &remaining_constraints,
+            ));
+        }
+    }
+    None
+}
 
-            fn constraints_for_color(
-                color: Color,
-                constraints: &[Constraint],
-                colors: &[Color],
-                mapping: &[usize],
-            ) -> Vec<Constraint> {
-                constraints
-                    .iter()
-                    .map(|constraint| translate_constraint(constraint, color, &colors, &mapping))
-                    .filter(|constraint| !constraint.cells.is_empty())
-                    .collect()
+// Like split, but uses the graph partitioner, which balances both parts and
+// minimizes the number of connecting constraints instead of trying cuts along
+// as few constraints as possible.
+fn split_partitioned(num_cells: usize, constraints: &[Constraint]) -> Option<SplitInput> {
+    let cells = constraints
+        .iter()
+        .map(|constraint| &constraint.cells[..])
+        .collect_vec();
+    let colors = partition::bisect(num_cells, &cells, 1)?
+        .into_iter()
+        .map(|in_first| if in_first { Color::Red } else { Color::Blue })
+        .collect_vec();
+    let (connecting_constraints, remaining_constraints): (Vec<_>, Vec<_>) =
+        constraints.iter().cloned().partition(|constraint| {
+            let mut cell_colors = constraint.cells.iter().map(|cell| colors[*cell]);
+            let first_color = cell_colors.next();
+            cell_colors.any(|color| Some(color) != first_color)
+        });
+    Some(split_by_colors(
+        colors,
+        &connecting_constraints,
+        &remaining_constraints,
+    ))
+}
+
+fn split_by_colors(
+    colors: Vec<Color>,
+    connecting_constraints: &[Constraint],
+    remaining_constraints: &[Constraint],
+) -> SplitInput {
+    // A vector that maps cell indizes from the original input to the
+    // cell indizes in the smaller parts.
+    let index_mapping = {
+        let mut red_counter = 0;
+        let mut blue_counter = 0;
+        let mut mapping: Vec<usize> = Default::default();
+        for color in &colors {
+            match color {
+                Color::Red => {
+                    mapping.push(red_counter);
+                    red_counter += 1;
+                }
+                Color::Blue => {
+                    mapping.push(blue_counter);
+                    blue_counter += 1;
+                }
             }
-            let all_constraints =
-                add_slices_to_small_vec(&connecting_constraints, &remaining_constraints);
-            return Some(SplitInput {
-                colors: colors.clone(),
-                index_mapping: index_mapping.clone(),
-                red_constraints: constraints_for_color(
-                    Color::Red,
-                    &all_constraints,
-                    &colors,
-                    &index_mapping,
-                ),
-                blue_constraints: constraints_for_color(
-                    Color::Blue,
-                    &all_constraints,
-                    &colors,
-                    &index_mapping,
-                ),
-                connections: connecting_constraints
-                    .iter()
-                    .map(|it| (*it).clone())
-                    .collect(),
-            });
         }
+        mapping
+    };
+
+    fn constraints_for_color(
+        color: Color,
+        constraints: &[Constraint],
+        colors: &[Color],
+        mapping: &[usize],
+    ) -> Vec<Constraint> {
+        constraints
+            .iter()
+            .map(|constraint| translate_constraint(constraint, color, &colors, &mapping))
+            .filter(|constraint| !constraint.cells.is_empty())
+            .collect()
+    }
+    let all_constraints = add_slices_to_small_vec(connecting_constraints, remaining_constraints);
+    SplitInput {
+        red_constraints: constraints_for_color(
+            Color::Red,
+            &all_constraints,
+            &colors,
+            &index_mapping,
+        ),
+        blue_constraints: constraints_for_color(
+            Color::Blue,
+            &all_constraints,
+            &colors,
+            &index_mapping,
+        ),
+        colors,
+        index_mapping,
+        connections: connecting_constraints.to_vec(),
     }
-    None
 }
 
 #[derive(Debug, Clone)]
@@ -176,6 +211,17 @@ enum QuasiSolution {
     },
 }
 impl QuasiSolution {
+    // The size of this node including the reference counts of the `Rc` it's
+    // stored in, but not including its children.
+    fn approximate_bytes(&self) -> usize {
+        size_of::<QuasiSolution>()
+            + 2 * size_of::<usize>()
+            + match self {
+                QuasiSolution::Concrete(concrete) => concrete.len() * size_of::<Value>(),
+                QuasiSolution::Plus(children) => children.len() * size_of::<Rc<QuasiSolution>>(),
+                QuasiSolution::Product { colors, .. } => colors.len() * size_of::<Color>(),
+            }
+    }
     fn size(&self) -> usize {
         match self {
             QuasiSolution::Concrete(_) => 1,
@@ -197,7 +243,7 @@ impl QuasiSolution {
             QuasiSolution::Product { colors, red, blue } => {
                 let red = Rc::new(red.simplify());
                 let blue = Rc::new(blue.simplify());
-                if red.size() == 1 || blue.size() == 1 {
+                if red.size() == 1 && blue.size() == 1 {
                     let mut red = red.build().pop().unwrap();
                     let mut blue = blue.build().pop().unwrap();
                     let mut solution: Vec<Value> = Default::default();
@@ -381,13 +427,55 @@ fn translate_constraint(
     }
 }
 
-pub fn solve(input: &game::Input) -> Output {
-    let constraints: Vec<Constraint> = input
+// Runs without a sum become constraints that allow any sum of different
+// digits.
+fn constraints(input: &game::Input) -> Vec<Constraint> {
+    input
         .constraints
         .iter()
         .map(|it| it.clone().into())
-        .collect();
-    let mut solutions = solve_rec(input.num_cells, &constraints, &[], "");
+        .chain(input.all_different.iter().map(|cells| Constraint {
+            cells: cells.iter().copied().collect(),
+            min: MINS[cells.len()],
+            max: MAXES[cells.len()],
+        }))
+        .collect()
+}
+
+type Split = fn(usize, &[Constraint]) -> Option<SplitInput>;
+
+pub fn solve_within(
+    input: &game::Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split, budget, monitor)
+}
+
+pub fn solve_partitioned_within(
+    input: &game::Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split_partitioned, budget, monitor)
+}
+
+fn solve_with_splitter(
+    input: &game::Input,
+    splitter: Split,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    let constraints = constraints(input);
+    let mut solutions = solve_rec(
+        input.num_cells,
+        &constraints,
+        &[],
+        splitter,
+        budget,
+        "",
+        monitor,
+    )?;
     let solutions = solutions.remove(&vec![]).unwrap();
     // log!("Solutions:\n{}", solutions));
     // log!("There are {} solutions.", solutions.size()));
@@ -395,7 +483,8 @@ pub fn solve(input: &game::Input) -> Output {
     let solutions = solutions.simplify();
     // log!("There are {} simple solutions.", solutions.size()));
     // log!("{}", &solutions));
-    solutions.build()
+    budget.allocate(solutions.size() * (size_of::<Solution>() + input.num_cells))?;
+    Ok(solutions.build())
 }
 
 // Takes a number of cells and all constraints. The additional information of
@@ -407,8 +496,12 @@ fn solve_rec(
     num_cells: usize,
     all_constraints: &[Constraint],
     connecting_constraints: &[Constraint],
+    splitter: Split,
+    budget: &MemoryBudget,
     log_prefix: &str,
-) -> FxHashMap<Vec<Vec9<Value>>, Rc<QuasiSolution>> {
+    monitor: &Monitor,
+) -> Result<FxHashMap<Vec<Vec9<Value>>, Rc<QuasiSolution>>, Error> {
+    monitor.visit()?;
     log!(
         "{}Solving input with {} cells and {} constraints to pay attention to: {:?}",
         log_prefix,
@@ -416,13 +509,14 @@ fn solve_rec(
         all_constraints.len(),
         all_constraints,
     );
-    let split = split(num_cells, all_constraints);
+    let split = splitter(num_cells, all_constraints);
 
     if split.is_none() {
         log!("{}Solving with simple algorithm.", log_prefix);
         debug_assert_eq!(num_cells, 1);
         let solutions = solve_one_cell(all_constraints);
         log!("{}Done. Found {} solutions.", log_prefix, solutions.len());
+        monitor.leaf(log_prefix.len() / 2, num_cells, solutions.len());
         let mut grouped = FxHashMap::<Vec<Vec9<Value>>, Rc<QuasiSolution>>::default();
         for solution in solutions {
             let key = connecting_constraints
@@ -434,17 +528,11 @@ fn solve_rec(
                     cells
                 })
                 .collect();
-            grouped
-                .entry(key)
-                .and_modify(|existing_solution| {
-                    *existing_solution = Rc::new(QuasiSolution::Plus(vec![
-                        Rc::new(QuasiSolution::Concrete(solution.clone())),
-                        existing_solution.clone(),
-                    ]));
-                })
-                .or_insert(Rc::new(QuasiSolution::Concrete(solution)));
+            let solution = QuasiSolution::Concrete(solution);
+            budget.allocate(solution.approximate_bytes())?;
+            insert_grouped(&mut grouped, key, Rc::new(solution), budget)?;
         }
-        return grouped;
+        return Ok(grouped);
     }
 
     let mut split = split.unwrap();
@@ -477,6 +565,13 @@ fn solve_rec(
         }
     }
 
+    monitor.split(
+        log_prefix.len() / 2,
+        red_mapping.len(),
+        blue_mapping.len(),
+        split_connecting_constraints.len(),
+    );
+
     // Solve parts.
     let inner_log_prefix = format!("{}  ", log_prefix);
     let red_solutions = solve_rec(
@@ -488,8 +583,11 @@ fn solve_rec(
                 translate_constraint(&constraint, Color::Red, &colors, &index_mapping)
             })
             .collect::<Vec<_>>(),
+        splitter,
+        budget,
         &inner_log_prefix,
-    );
+        monitor,
+    )?;
     let blue_solutions = solve_rec(
         blue_mapping.len(),
         &blue_constraints,
@@ -499,16 +597,20 @@ fn solve_rec(
                 translate_constraint(&constraint, Color::Blue, &colors, &index_mapping)
             })
             .collect::<Vec<_>>(),
+        splitter,
+        budget,
         &inner_log_prefix,
-    );
+        monitor,
+    )?;
 
     // Combine results.
     log!(
-        "{}Combining {}x{} solutions with {} connections.",
+        depth = log_prefix.len() / 2,
+        red = red_solutions.len(),
+        blue = blue_solutions.len(),
+        connections = split_connecting_constraints.len();
+        "{}Combining solutions.",
         log_prefix,
-        red_solutions.len(),
-        blue_solutions.len(),
-        split_connecting_constraints.len(),
     );
     log!(
         "{}Naively joining solutions would require checking {} candidates.",
@@ -518,6 +620,7 @@ fn solve_rec(
     let mut solutions: Vec<(Vec<Vec9<Value>>, Rc<QuasiSolution>)> = Default::default();
     for (red_connecting_values, red_solution) in &red_solutions {
         'solutions: for (blue_connecting_values, blue_solution) in &blue_solutions {
+            monitor.visit()?;
             for ((constraint, red_values), blue_values) in split_connecting_constraints
                 .iter()
                 .zip(
@@ -537,6 +640,7 @@ fn solve_rec(
                 }
             }
             log!(
+                Trace;
                 "{}  Combining red {:?} and blue {:?} works.",
                 log_prefix,
                 red_connecting_values,
@@ -548,26 +652,32 @@ fn solve_rec(
                 .zip(blue_connecting_values)
                 .map(|((_, red_values), blue_values)| add_slices_to_vec9(red_values, blue_values))
                 .collect();
-            let value = Rc::new(QuasiSolution::Product {
+            let value = QuasiSolution::Product {
                 colors: colors.clone(),
                 red: red_solution.clone(),
                 blue: blue_solution.clone(),
-            });
-            solutions.push((key, value));
+            };
+            budget.allocate(approximate_key_bytes(&key) + value.approximate_bytes())?;
+            solutions.push((key, Rc::new(value)));
         }
     }
 
+    monitor.merged(
+        log_prefix.len() / 2,
+        red_solutions.len() * blue_solutions.len(),
+        solutions.len(),
+    );
+
     let mut grouped = FxHashMap::<Vec<Vec9<Value>>, Rc<QuasiSolution>>::default();
     for (key, solution) in solutions {
-        grouped
-            .entry(key)
-            .and_modify(|existing_solution| {
-                *existing_solution = Rc::new(QuasiSolution::Plus(vec![
-                    solution.clone(),
-                    existing_solution.clone(),
-                ]));
-            })
-            .or_insert(solution);
+        // The key was already accounted for when the solution was created.
+        budget.free(approximate_key_bytes(&key));
+        insert_grouped(&mut grouped, key, solution, budget)?;
+    }
+    // The maps of the parts are dropped, but their solutions live on as part
+    // of the combined ones.
+    for key in red_solutions.keys().chain(blue_solutions.keys()) {
+        budget.free(approximate_key_bytes(key));
     }
 
     log!(
@@ -578,5 +688,31 @@ fn solve_rec(
             .map(|(_, solution)| solution.size())
             .sum::<usize>()
     );
-    grouped
+    Ok(grouped)
+}
+
+fn insert_grouped(
+    grouped: &mut FxHashMap<Vec<Vec9<Value>>, Rc<QuasiSolution>>,
+    key: Vec<Vec9<Value>>,
+    solution: Rc<QuasiSolution>,
+    budget: &MemoryBudget,
+) -> Result<(), Error> {
+    match grouped.get_mut(&key) {
+        Some(existing_solution) => {
+            let plus = QuasiSolution::Plus(vec![solution, existing_solution.clone()]);
+            budget.allocate(plus.approximate_bytes())?;
+            *existing_solution = Rc::new(plus);
+        }
+        None => {
+            budget.allocate(approximate_key_bytes(&key))?;
+            grouped.insert(key, solution);
+        }
+    }
+    Ok(())
+}
+
+// The size of a key in a map of grouped solutions, including the entry's
+// pointer to the solution.
+fn approximate_key_bytes(key: &[Vec9<Value>]) -> usize {
+    size_of::<Vec<Vec9<Value>>>() + size_of_val(key) + size_of::<Rc<QuasiSolution>>()
 }
@@ -1,9 +1,10 @@
+use super::{Error, MemoryBudget, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
     log,
 };
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::{collections::HashMap, mem::size_of};
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 enum Color {
@@ -100,6 +101,7 @@ fn split(input: &Input) -> Option<SplitInput> {
             };
 
             fn create_sub_input(
+                input: &Input,
                 color: Color,
                 colors: &[Color],
                 constraints: &[Constraint],
@@ -119,13 +121,22 @@ fn split(input: &Input) -> Option<SplitInput> {
                             sum: constraint.sum,
                         })
                         .collect(),
+                    all_different: input
+                        .all_different_within(|cell| colors[cell] == color, index_mapping),
                 }
             }
             return Some(SplitInput {
                 colors: colors.clone(),
                 index_mapping: index_mapping.clone(),
-                red: create_sub_input(Color::Red, &colors, &remaining_constraints, &index_mapping),
+                red: create_sub_input(
+                    input,
+                    Color::Red,
+                    &colors,
+                    &remaining_constraints,
+                    &index_mapping,
+                ),
                 blue: create_sub_input(
+                    input,
                     Color::Blue,
                     &colors,
                     &remaining_constraints,
@@ -154,11 +165,26 @@ fn do_digits_satisfy_sum(digits: &[Value], sum: Value) -> bool {
     digits.into_iter().sum::<Value>() == sum
 }
 
-pub fn solve(input: &Input) -> Output {
-    solve_rec(input, "")
+pub fn solve_within(
+    input: &Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_rec(input, "", budget, monitor)
+}
+
+// The approximate size of a solution with this many cells.
+fn approximate_solution_bytes(num_cells: usize) -> usize {
+    size_of::<Solution>() + num_cells * size_of::<Value>()
 }
 
-fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
+fn solve_rec(
+    input: &Input,
+    log_prefix: &str,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Vec<Solution>, Error> {
+    monitor.visit()?;
     log!(
         "{}Solving input with {} cells and {} constraints.",
         log_prefix,
@@ -169,9 +195,13 @@ fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
 
     if split.is_none() {
         log!("{}Solving with simple solver.", log_prefix);
-        let solutions = super::sum_reachable_no_set::solve(input);
+        let mut solutions =
+            super::sum_reachable_no_set::solve(input, &monitor.without_recording())?;
+        solutions.retain(|solution| input.has_different_digits(solution));
+        budget.allocate(solutions.len() * approximate_solution_bytes(input.num_cells))?;
         log!("{}Done. Found {} solutions.", log_prefix, solutions.len());
-        return solutions;
+        monitor.leaf(log_prefix.len() / 2, input.num_cells, solutions.len());
+        return Ok(solutions);
     }
 
     let mut split = split.unwrap();
@@ -201,23 +231,35 @@ fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
         }
     }
 
+    monitor.split(
+        log_prefix.len() / 2,
+        red_to_original_mapping.len(),
+        blue_to_original_mapping.len(),
+        connections.len(),
+    );
+
     // Solve parts.
     let inner_log_prefix = format!("{}  ", log_prefix);
-    let red_solutions = solve_rec(&red, &inner_log_prefix);
-    let blue_solutions = solve_rec(&blue, &inner_log_prefix);
+    let red_solutions = solve_rec(&red, &inner_log_prefix, budget, monitor)?;
+    let blue_solutions = solve_rec(&blue, &inner_log_prefix, budget, monitor)?;
 
     // Combine results.
     log!(
-        "{}Combining {}x{} solutions with {} connections.",
+        depth = log_prefix.len() / 2,
+        red = red_solutions.len(),
+        blue = blue_solutions.len(),
+        connections = connections.len();
+        "{}Combining solutions.",
         log_prefix,
-        red_solutions.len(),
-        blue_solutions.len(),
-        connections.len(),
     );
+    let num_candidates = red_solutions.len() * blue_solutions.len();
+    let part_bytes = red_solutions.len()
+        * approximate_solution_bytes(red_to_original_mapping.len())
+        + blue_solutions.len() * approximate_solution_bytes(blue_to_original_mapping.len());
     log!(
         "{}Naively joining solutions would require checking {} candidates.",
         log_prefix,
-        red_solutions.len() * blue_solutions.len()
+        num_candidates
     );
 
     fn get_solutions_by_sums(
@@ -266,6 +308,7 @@ fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
     let mut solutions = vec![];
     for (red_connecting_values, red_solutions) in &red_solutions_by_sums {
         'solutions: for (blue_connecting_values, blue_solutions) in &blue_solutions_by_sums {
+            monitor.visit()?;
             for ((red, blue), constraint) in red_connecting_values
                 .iter()
                 .zip(blue_connecting_values)
@@ -279,6 +322,7 @@ fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
                 }
             }
             log!(
+                Trace;
                 "{}  Combining red {:?} and blue {:?} works and yields {}x{} = {} candidates.",
                 log_prefix,
                 red_connecting_values,
@@ -289,6 +333,7 @@ fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
             );
             for red_solution in red_solutions {
                 for blue_solution in blue_solutions {
+                    monitor.visit()?;
                     let mut attempt = vec![0; input.num_cells];
                     for (i, value) in red_solution.iter().enumerate() {
                         attempt[red_to_original_mapping[i]] = *value;
@@ -296,12 +341,19 @@ fn solve_rec(input: &Input, log_prefix: &str) -> Vec<Solution> {
                     for (i, value) in blue_solution.iter().enumerate() {
                         attempt[blue_to_original_mapping[i]] = *value;
                     }
-                    solutions.push(attempt);
+                    // Runs without a sum may contain cells of both parts.
+                    if input.has_different_digits(&attempt) {
+                        budget.allocate(approximate_solution_bytes(input.num_cells))?;
+                        solutions.push(attempt);
+                    }
                 }
             }
         }
     }
 
+    // The solutions of the parts are dropped.
+    budget.free(part_bytes);
+    monitor.merged(log_prefix.len() / 2, num_candidates, solutions.len());
     log!("{}Done. Found {} solutions.", log_prefix, solutions.len());
-    solutions
+    Ok(solutions)
 }
@@ -1,6 +1,7 @@
+use super::{Error, MemoryBudget, Monitor};
 use crate::{
     game::{self, Output, Solution, Value},
-    log,
+    log, partition,
 };
 use arrayvec::ArrayVec;
 use itertools::Itertools;
@@ -8,6 +9,7 @@ use rustc_hash::FxHashMap;
 use std::{
     cmp::max,
     fmt::{self, Display},
+    mem::{size_of, size_of_val},
     rc::Rc,
 };
 
@@ -109,64 +111,97 @@ fn split(num_cells: usize, constraints: &[Constraint]) -> Option<SplitInput> {
                 }
             }
 
-            // A vector that maps cell indizes from the original input to the
-            // cell indizes in the smaller parts.
-            let index_mapping = {
-                let mut red_counter = 0;
-                let mut blue_counter = 0;
-                let mut mapping: Vec<usize> = Default::default();
-                for i in 0..num_cells {
-                    match colors[i] {
-                        Color::Red => {
-                            mapping.push(red_counter);
-                            red_counter += 1;
-                        }
-                        Color::Blue => {
-                            mapping.push(blue_counter);
-                            blue_counter += 1;
-                        }
-                    }
-                }
-                mapping
-            };
+            return Some(split_by_colors(
+                colors,
+                &connecting_constraints,
+                &remaining_constraints,
+            ));
+        }
+    }
+    None
+}
 
-            fn constraints_for_color(
-                color: Color,
-                constraints: &[Constraint],
-                colors: &[Color],
-                mapping: &[usize],
-            ) -> Vec<Constraint> {
-                constraints
-                    .iter()
-                    .map(|constraint| translate_constraint(constraint, color, &colors, &mapping))
-                    .filter(|constraint| !constraint.cells.is_empty())
-                    .collect()
+// Like split, but uses a partitioner that balances both parts and minimizes the
+// number of connecting constraints instead of only trying cuts along zero or
+// one constraint.
+fn split_partitioned(num_cells: usize, constraints: &[Constraint]) -> Option<SplitInput> {
+    let cells: Vec<&[usize]> = constraints
+        .iter()
+        .map(|constraint| constraint.cells.as_slice())
+        .collect();
+    let colors: Vec<Color> = partition::bisect(num_cells, &cells, 3)?
+        .into_iter()
+        .map(|in_first| if in_first { Color::Red } else { Color::Blue })
+        .collect();
+    let (connecting_constraints, remaining_constraints): (Vec<_>, Vec<_>) =
+        constraints.iter().cloned().partition(|constraint| {
+            let mut cell_colors = constraint.cells.iter().map(|cell| colors[*cell]);
+            let first_color = cell_colors.next();
+            cell_colors.any(|color| Some(color) != first_color)
+        });
+    Some(split_by_colors(
+        colors,
+        &connecting_constraints,
+        &remaining_constraints,
+    ))
+}
+
+fn split_by_colors(
+    colors: Vec<Color>,
+    connecting_constraints: &[Constraint],
+    remaining_constraints: &[Constraint],
+) -> SplitInput {
+    // A vector that maps cell indizes from the original input to the cell
+    // indizes in the smaller parts.
+    let index_mapping = {
+        let mut red_counter = 0;
+        let mut blue_counter = 0;
+        let mut mapping: Vec<usize> = Default::default();
+        for color in &colors {
+            match color {
+                Color::Red => {
+                    mapping.push(red_counter);
+                    red_counter += 1;
+                }
+                Color::Blue => {
+                    mapping.push(blue_counter);
+                    blue_counter += 1;
+                }
             }
-            let all_constraints =
-                add_slices_to_small_vec(&connecting_constraints, &remaining_constraints);
-            return Some(SplitInput {
-                colors: colors.clone(),
-                index_mapping: index_mapping.clone(),
-                red_constraints: constraints_for_color(
-                    Color::Red,
-                    &all_constraints,
-                    &colors,
-                    &index_mapping,
-                ),
-                blue_constraints: constraints_for_color(
-                    Color::Blue,
-                    &all_constraints,
-                    &colors,
-                    &index_mapping,
-                ),
-                connections: connecting_constraints
-                    .iter()
-                    .map(|it| (*it).clone())
-                    .collect(),
-            });
         }
+        mapping
+    };
+
+    fn constraints_for_color(
+        color: Color,
+        constraints: &[Constraint],
+        colors: &[Color],
+        mapping: &[usize],
+    ) -> Vec<Constraint> {
+        constraints
+            .iter()
+            .map(|constraint| translate_constraint(constraint, color, colors, mapping))
+            .filter(|constraint| !constraint.cells.is_empty())
+            .collect()
+    }
+    let all_constraints = add_slices_to_small_vec(connecting_constraints, remaining_constraints);
+    SplitInput {
+        red_constraints: constraints_for_color(
+            Color::Red,
+            &all_constraints,
+            &colors,
+            &index_mapping,
+        ),
+        blue_constraints: constraints_for_color(
+            Color::Blue,
+            &all_constraints,
+            &colors,
+            &index_mapping,
+        ),
+        colors,
+        index_mapping,
+        connections: connecting_constraints.to_vec(),
     }
-    None
 }
 
 #[derive(Debug, Clone)]
@@ -180,6 +215,17 @@ enum QuasiSolution {
     },
 }
 impl QuasiSolution {
+    // The size of this node including the reference counts of the `Rc` it's
+    // stored in, but not including its children.
+    fn approximate_bytes(&self) -> usize {
+        size_of::<QuasiSolution>()
+            + 2 * size_of::<usize>()
+            + match self {
+                QuasiSolution::Concrete(concrete) => concrete.len() * size_of::<Value>(),
+                QuasiSolution::Plus(children) => children.len() * size_of::<Rc<QuasiSolution>>(),
+                QuasiSolution::Product { colors, .. } => colors.len() * size_of::<Color>(),
+            }
+    }
     fn size(&self) -> usize {
         match self {
             QuasiSolution::Concrete(_) => 1,
@@ -201,7 +247,7 @@ impl QuasiSolution {
             QuasiSolution::Product { colors, red, blue } => {
                 let red = Rc::new(red.simplify());
                 let blue = Rc::new(blue.simplify());
-                if red.size() == 1 || blue.size() == 1 {
+                if red.size() == 1 && blue.size() == 1 {
                     let mut red = red.build().pop().unwrap();
                     let mut blue = blue.build().pop().unwrap();
                     let mut solution: Vec<Value> = Default::default();
@@ -383,6 +429,7 @@ mod simple_solver {
         solutions: &mut Vec<Solution>,
     ) {
         log!(
+            Trace;
             "Evaluating attempt {}",
             attempt
                 .iter()
@@ -479,13 +526,74 @@ fn translate_constraint(
     }
 }
 
-pub fn solve(input: &game::Input) -> Output {
-    let constraints: Vec<Constraint> = input
+// Runs without a sum become constraints that allow any sum of different
+// digits.
+fn constraints(input: &game::Input) -> Vec<Constraint> {
+    input
         .constraints
         .iter()
         .map(|it| it.clone().into())
-        .collect();
-    let mut solutions = solve_rec(input.num_cells, &constraints, &[], "");
+        .chain(input.all_different.iter().map(|cells| Constraint {
+            cells: cells.iter().copied().collect(),
+            min: MINS[cells.len()],
+            max: MAXES[cells.len()],
+        }))
+        .collect()
+}
+
+type Split = fn(usize, &[Constraint]) -> Option<SplitInput>;
+
+pub fn solve_within(
+    input: &game::Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split, budget, monitor)
+}
+
+pub fn solve_partitioned_within(
+    input: &game::Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split_partitioned, budget, monitor)
+}
+
+/// The colors that the first split of `solve_within` or `solve_partitioned_within` gives the
+/// cells, `true` meaning red. Returns `None` if the input isn't split.
+pub fn split_colors(input: &game::Input, partitioned: bool) -> Option<Vec<bool>> {
+    let constraints = constraints(input);
+    let splitter: Split = if partitioned {
+        split_partitioned
+    } else {
+        split
+    };
+    let split_input = splitter(input.num_cells, &constraints)?;
+    Some(
+        split_input
+            .colors
+            .into_iter()
+            .map(|color| color == Color::Red)
+            .collect(),
+    )
+}
+
+fn solve_with_splitter(
+    input: &game::Input,
+    splitter: Split,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    let constraints = constraints(input);
+    let mut solutions = solve_rec(
+        input.num_cells,
+        &constraints,
+        &[],
+        splitter,
+        budget,
+        monitor,
+        "",
+    )?;
     let solutions = solutions.remove(&vec![]).unwrap();
     // log!("Solutions:\n{}", solutions));
     // log!("There are {} solutions.", solutions.size()));
@@ -493,7 +601,8 @@ pub fn solve(input: &game::Input) -> Output {
     let solutions = solutions.simplify();
     // log!("There are {} simple solutions.", solutions.size()));
     // log!("{}", &solutions));
-    solutions.build()
+    budget.allocate(solutions.size() * (size_of::<Solution>() + input.num_cells))?;
+    Ok(solutions.build())
 }
 
 // Takes a number of cells and all constraints. The additional information of
@@ -505,8 +614,12 @@ fn solve_rec(
     num_cells: usize,
     all_constraints: &[Constraint],
     connecting_constraints: &[Constraint],
+    splitter: Split,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
     log_prefix: &str,
-) -> FxHashMap<Vec<Vec9<Value>>, Rc<QuasiSolution>> {
+) -> Result<FxHashMap<Vec<Vec9<Value>>, Rc<QuasiSolution>>, Error> {
+    monitor.visit()?;
     log!(
         "{}Solving input with {} cells and {} constraints to pay attention to: {:?}",
         log_prefix,
@@ -514,16 +627,16 @@ fn solve_rec(
         all_constraints.len(),
         all_constraints,
     );
-    let split = split(num_cells, all_constraints);
+    let split = splitter(num_cells, all_constraints);
 
     if split.is_none() {
         log!("{}Solving with simple algorithm.", log_prefix);
-        debug_assert_eq!(num_cells, 1);
         let solutions = simple_solver::solve(num_cells, all_constraints);
         log!("{}Done. Found {} solutions.", log_prefix, solutions.len());
+        monitor.leaf(log_prefix.len() / 2, num_cells, solutions.len());
         let mut grouped = FxHashMap::<Vec<Vec9<Value>>, Rc<QuasiSolution>>::default();
         for solution in solutions {
-            let key = connecting_constraints
+            let key: Vec<Vec9<Value>> = connecting_constraints
                 .iter()
                 .map(|connection| {
                     let mut cells: Vec9<Value> =
@@ -532,17 +645,11 @@ fn solve_rec(
                     cells
                 })
                 .collect();
-            grouped
-                .entry(key)
-                .and_modify(|existing_solution| {
-                    *existing_solution = Rc::new(QuasiSolution::Plus(vec![
-                        Rc::new(QuasiSolution::Concrete(solution.clone())),
-                        existing_solution.clone(),
-                    ]));
-                })
-                .or_insert(Rc::new(QuasiSolution::Concrete(solution)));
+            let solution = QuasiSolution::Concrete(solution);
+            budget.allocate(solution.approximate_bytes())?;
+            insert_grouped(&mut grouped, key, Rc::new(solution), budget)?;
         }
-        return grouped;
+        return Ok(grouped);
     }
 
     let mut split = split.unwrap();
@@ -575,6 +682,13 @@ fn solve_rec(
         }
     }
 
+    monitor.split(
+        log_prefix.len() / 2,
+        red_mapping.len(),
+        blue_mapping.len(),
+        split_connecting_constraints.len(),
+    );
+
     // Solve parts.
     let inner_log_prefix = format!("{}  ", log_prefix);
     let red_solutions = solve_rec(
@@ -586,8 +700,11 @@ fn solve_rec(
                 translate_constraint(&constraint, Color::Red, &colors, &index_mapping)
             })
             .collect::<Vec<_>>(),
+        splitter,
+        budget,
+        monitor,
         &inner_log_prefix,
-    );
+    )?;
     let blue_solutions = solve_rec(
         blue_mapping.len(),
         &blue_constraints,
@@ -597,16 +714,20 @@ fn solve_rec(
                 translate_constraint(&constraint, Color::Blue, &colors, &index_mapping)
             })
             .collect::<Vec<_>>(),
+        splitter,
+        budget,
+        monitor,
         &inner_log_prefix,
-    );
+    )?;
 
     // Combine results.
     log!(
-        "{}Combining {}x{} solutions with {} connections.",
+        depth = log_prefix.len() / 2,
+        red = red_solutions.len(),
+        blue = blue_solutions.len(),
+        connections = split_connecting_constraints.len();
+        "{}Combining solutions.",
         log_prefix,
-        red_solutions.len(),
-        blue_solutions.len(),
-        split_connecting_constraints.len(),
     );
     log!(
         "{}Naively joining solutions would require checking {} candidates.",
@@ -616,6 +737,7 @@ fn solve_rec(
     let mut solutions: Vec<(Vec<Vec9<Value>>, Rc<QuasiSolution>)> = Default::default();
     for (red_connecting_values, red_solution) in &red_solutions {
         'solutions: for (blue_connecting_values, blue_solution) in &blue_solutions {
+            monitor.visit()?;
             for ((constraint, red_values), blue_values) in split_connecting_constraints
                 .iter()
                 .zip(
@@ -635,6 +757,7 @@ fn solve_rec(
                 }
             }
             log!(
+                Trace;
                 "{}  Combining red {:?} and blue {:?} works.",
                 log_prefix,
                 red_connecting_values,
@@ -644,28 +767,40 @@ fn solve_rec(
                 .iter()
                 .zip(red_connecting_values)
                 .zip(blue_connecting_values)
-                .map(|((_, red_values), blue_values)| add_slices_to_vec9(red_values, blue_values))
+                .map(|((_, red_values), blue_values)| {
+                    // Sorted like the keys of the leaves, so that solutions
+                    // using the same digits end up in the same group.
+                    let mut values = add_slices_to_vec9(red_values, blue_values);
+                    values.sort();
+                    values
+                })
                 .collect();
-            let value = Rc::new(QuasiSolution::Product {
+            let value = QuasiSolution::Product {
                 colors: colors.clone(),
                 red: red_solution.clone(),
                 blue: blue_solution.clone(),
-            });
-            solutions.push((key, value));
+            };
+            budget.allocate(approximate_key_bytes(&key) + value.approximate_bytes())?;
+            solutions.push((key, Rc::new(value)));
         }
     }
 
+    monitor.merged(
+        log_prefix.len() / 2,
+        red_solutions.len() * blue_solutions.len(),
+        solutions.len(),
+    );
+
     let mut grouped = FxHashMap::<Vec<Vec9<Value>>, Rc<QuasiSolution>>::default();
     for (key, solution) in solutions {
-        grouped
-            .entry(key)
-            .and_modify(|existing_solution| {
-                *existing_solution = Rc::new(QuasiSolution::Plus(vec![
-                    solution.clone(),
-                    existing_solution.clone(),
-                ]));
-            })
-            .or_insert(solution);
+        // The key was already accounted for when the solution was created.
+        budget.free(approximate_key_bytes(&key));
+        insert_grouped(&mut grouped, key, solution, budget)?;
+    }
+    // The maps of the parts are dropped, but their solutions live on as part
+    // of the combined ones.
+    for key in red_solutions.keys().chain(blue_solutions.keys()) {
+        budget.free(approximate_key_bytes(key));
     }
 
     log!(
@@ -676,5 +811,55 @@ fn solve_rec(
             .map(|(_, solution)| solution.size())
             .sum::<usize>()
     );
-    grouped
+    Ok(grouped)
+}
+
+fn insert_grouped(
+    grouped: &mut FxHashMap<Vec<Vec9<Value>>, Rc<QuasiSolution>>,
+    key: Vec<Vec9<Value>>,
+    solution: Rc<QuasiSolution>,
+    budget: &MemoryBudget,
+) -> Result<(), Error> {
+    match grouped.get_mut(&key) {
+        Some(existing_solution) => {
+            let plus = QuasiSolution::Plus(vec![solution, existing_solution.clone()]);
+            budget.allocate(plus.approximate_bytes())?;
+            *existing_solution = Rc::new(plus);
+        }
+        None => {
+            budget.allocate(approximate_key_bytes(&key))?;
+            grouped.insert(key, solution);
+        }
+    }
+    Ok(())
+}
+
+// The size of a key in a map of grouped solutions, including the entry's
+// pointer to the solution.
+fn approximate_key_bytes(key: &[Vec9<Value>]) -> usize {
+    size_of::<Vec<Vec9<Value>>>() + size_of_val(key) + size_of::<Rc<QuasiSolution>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::ParseBoard;
+
+    // The red part of the first partitioned split has one solution and the
+    // blue part two, which used to be simplified into a single solution.
+    #[test]
+    fn finds_all_solutions_of_products() {
+        let input = concat!("\\ 3\\ \\ 8\\ 8\\\n", "\\1 _ 1\\3 _ _\n", "\\16 _ _ _ _",)
+            .parse_board()
+            .unwrap()
+            .to_input();
+        let expected = vec![vec![1, 1, 2, 2, 1, 7, 6], vec![1, 2, 1, 2, 1, 6, 7]];
+        let budget = MemoryBudget::unlimited();
+        let monitor = Monitor::unlimited();
+        for solve in [solve_within, solve_partitioned_within] {
+            let mut solutions = solve(&input, &budget, &monitor).unwrap();
+            solutions.sort();
+            assert_eq!(solutions, expected);
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Input, Output, Solution, Value, Constraint},
     log,
@@ -29,15 +30,22 @@ impl ConstraintExt for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt: Vec<Option<Value>> = vec![None; input.num_cells];
     let mut solutions = vec![];
-    solve_rec(input, &mut attempt, &mut solutions);
-    solutions
+    solve_rec(input, &mut attempt, &mut solutions, monitor)?;
+    Ok(solutions)
 }
 
-fn solve_rec(input: &Input, attempt: &mut Vec<Option<Value>>, solutions: &mut Vec<Solution>) {
+fn solve_rec(
+    input: &Input,
+    attempt: &mut Vec<Option<Value>>,
+    solutions: &mut Vec<Solution>,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
     log!(
+        Trace;
         "Evaluating attempt {}",
         attempt
             .iter()
@@ -51,18 +59,22 @@ fn solve_rec(input: &Input, attempt: &mut Vec<Option<Value>>, solutions: &mut Ve
     );
 
     if !input.is_possible_solution(attempt) {
-        return;
+        monitor.fail(None);
+        return Ok(());
     }
 
     let first_empty_cell_index = attempt.iter().position(|it| it.is_none());
     if let Some(index) = first_empty_cell_index {
         for i in 1..=9 {
+            monitor.assign(index, index, i);
             attempt[index] = Some(i);
-            solve_rec(input, attempt, solutions);
+            solve_rec(input, attempt, solutions, monitor)?;
         }
         attempt[index] = None;
     } else {
         // This is a solution.
         solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Value},
     log,
@@ -42,7 +43,7 @@ impl ConstraintExt for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt = vec![None; input.num_cells];
     let mut solutions = vec![];
     let mut affected_constraints = HashMap::new();
@@ -54,9 +55,11 @@ pub fn solve(input: &Input) -> Output {
 
     let mut current_cell = 0;
     'outer: loop {
+        monitor.visit()?;
         if current_cell == input.num_cells {
             // No cell is free anymore. We have a solution.
             solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+            monitor.found_solution();
             current_cell -= 1;
         } else {
             attempt[current_cell] = match attempt[current_cell] {
@@ -66,6 +69,7 @@ pub fn solve(input: &Input) -> Output {
             };
 
             log!(
+                Trace;
                 "Evaluating attempt {} (current is {})",
                 attempt
                     .iter()
@@ -79,18 +83,20 @@ pub fn solve(input: &Input) -> Output {
                 current_cell
             );
 
-            if attempt[current_cell].is_none() {
-                if current_cell == 0 {
-                    break;
-                } else {
+            let digit = match attempt[current_cell] {
+                Some(digit) => digit,
+                None if current_cell == 0 => break,
+                None => {
                     current_cell -= 1;
                     continue;
                 }
-            }
+            };
+            monitor.assign(current_cell, current_cell, digit);
 
             for constraint_index in &affected_constraints[&current_cell] {
                 let constraint = &input.constraints[*constraint_index];
                 if !constraint.is_satisfied_by(&attempt) {
+                    monitor.fail(Some(*constraint_index));
                     continue 'outer;
                 }
             }
@@ -98,5 +104,5 @@ pub fn solve(input: &Input) -> Output {
         }
     }
 
-    solutions
+    Ok(solutions)
 }
@@ -1,10 +1,15 @@
+use super::{Error, MemoryBudget, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
-    log,
+    log, partition,
 };
 use itertools::Itertools;
 use num_bigint::{BigUint, ToBigUint};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    mem::{size_of, size_of_val},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 enum Color {
@@ -79,67 +84,115 @@ fn split(input: &Input) -> Option<SplitInput> {
                 continue;
             }
 
-            // A vector that maps cell indizes from the original input to the
-            // cell indizes in the smaller parts.
-            let index_mapping = {
-                let mut red_counter = 0;
-                let mut blue_counter = 0;
-                let mut mapping = vec![];
-                for i in 0..input.num_cells {
-                    match colors[i] {
-                        Color::Red => {
-                            mapping.push(red_counter);
-                            red_counter += 1;
-                        }
-                        Color::Blue => {
-                            mapping.push(blue_counter);
-                            blue_counter += 1;
-                        }
-                    }
+            let connecting_constraints = connecting_constraints.into_iter().cloned().collect_vec();
+            return Some(split_by_colors(
+                input,
+                colors,
+                &connecting_constraints,
+                &remaining_constraints,
+            ));
+        }
+    }
+    None
+}
+
+// Like split, but uses the graph partitioner, which balances both parts and
+// minimizes the number of connecting constraints instead of trying cuts along
+// as few constraints as possible.
+fn split_partitioned(input: &Input) -> Option<SplitInput> {
+    let cells = input
+        .constraints
+        .iter()
+        .map(|constraint| &constraint.cells[..])
+        .collect_vec();
+    let colors = partition::bisect(input.num_cells, &cells, 1)?
+        .into_iter()
+        .map(|in_first| if in_first { Color::Red } else { Color::Blue })
+        .collect_vec();
+    let (connecting_constraints, remaining_constraints): (Vec<_>, Vec<_>) =
+        input.constraints.iter().cloned().partition(|constraint| {
+            let mut cell_colors = constraint.cells.iter().map(|cell| colors[*cell]);
+            let first_color = cell_colors.next();
+            cell_colors.any(|color| Some(color) != first_color)
+        });
+    Some(split_by_colors(
+        input,
+        colors,
+        &connecting_constraints,
+        &remaining_constraints,
+    ))
+}
+
+fn split_by_colors(
+    input: &Input,
+    colors: Vec<Color>,
+    connecting_constraints: &[Constraint],
+    remaining_constraints: &[Constraint],
+) -> SplitInput {
+    // A vector that maps cell indizes from the original input to the
+    // cell indizes in the smaller parts.
+    let index_mapping = {
+        let mut red_counter = 0;
+        let mut blue_counter = 0;
+        let mut mapping = vec![];
+        for color in &colors {
+            match color {
+                Color::Red => {
+                    mapping.push(red_counter);
+                    red_counter += 1;
                 }
-                mapping
-            };
+                Color::Blue => {
+                    mapping.push(blue_counter);
+                    blue_counter += 1;
+                }
+            }
+        }
+        mapping
+    };
 
-            fn create_sub_input(
-                color: Color,
-                colors: &[Color],
-                constraints: &[Constraint],
-                index_mapping: &[usize],
-            ) -> Input {
-                Input {
-                    num_cells: colors.iter().filter(|it| **it == color).count(),
-                    constraints: constraints
+    fn create_sub_input(
+        input: &Input,
+        color: Color,
+        colors: &[Color],
+        constraints: &[Constraint],
+        index_mapping: &[usize],
+    ) -> Input {
+        Input {
+            num_cells: colors.iter().filter(|it| **it == color).count(),
+            constraints: constraints
+                .iter()
+                .filter(|constraint| colors[constraint.cells[0]] == color)
+                .map(|constraint| Constraint {
+                    cells: constraint
+                        .cells
                         .iter()
-                        .filter(|constraint| colors[constraint.cells[0]] == color)
-                        .map(|constraint| Constraint {
-                            cells: constraint
-                                .cells
-                                .iter()
-                                .map(|cell| index_mapping[*cell])
-                                .collect(),
-                            sum: constraint.sum,
-                        })
+                        .map(|cell| index_mapping[*cell])
                         .collect(),
-                }
-            }
-            return Some(SplitInput {
-                colors: colors.clone(),
-                index_mapping: index_mapping.clone(),
-                red: create_sub_input(Color::Red, &colors, &remaining_constraints, &index_mapping),
-                blue: create_sub_input(
-                    Color::Blue,
-                    &colors,
-                    &remaining_constraints,
-                    &index_mapping,
-                ),
-                connections: connecting_constraints
-                    .iter()
-                    .map(|it| (*it).clone())
-                    .collect(),
-            });
+                    sum: constraint.sum,
+                })
+                .collect(),
+            all_different: input.all_different_within(|cell| colors[cell] == color, index_mapping),
         }
     }
-    None
+    SplitInput {
+        red: create_sub_input(
+            input,
+            Color::Red,
+            &colors,
+            remaining_constraints,
+            &index_mapping,
+        ),
+        blue: create_sub_input(
+            input,
+            Color::Blue,
+            &colors,
+            remaining_constraints,
+            &index_mapping,
+        ),
+        colors,
+        index_mapping,
+        connections: connecting_constraints.to_vec(),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -153,6 +206,22 @@ enum QuasiSolution {
     },
 }
 impl QuasiSolution {
+    // The size of this solution including all of its children, which aren't
+    // shared but copied into each product.
+    fn approximate_bytes(&self) -> usize {
+        size_of::<QuasiSolution>()
+            + match self {
+                QuasiSolution::Concrete(concrete) => concrete.len() * size_of::<Value>(),
+                QuasiSolution::Plus(children) => {
+                    children.iter().map(|it| it.approximate_bytes()).sum()
+                }
+                QuasiSolution::Product { colors, red, blue } => {
+                    colors.len() * size_of::<Color>()
+                        + red.approximate_bytes()
+                        + blue.approximate_bytes()
+                }
+            }
+    }
     fn size(&self) -> BigUint {
         match self {
             QuasiSolution::Concrete(_) => 1u8.to_biguint().unwrap(),
@@ -203,19 +272,52 @@ fn do_digits_satisfy_sum(digits: &[Value], sum: Value) -> bool {
     digits.into_iter().sum::<Value>() == sum
 }
 
-pub fn solve(input: &Input) -> Output {
-    let mut solutions = solve_rec(input, &vec![], "");
+type Split = fn(&Input) -> Option<SplitInput>;
+
+pub fn solve_within(
+    input: &Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split, budget, monitor)
+}
+
+pub fn solve_partitioned_within(
+    input: &Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split_partitioned, budget, monitor)
+}
+
+fn solve_with_splitter(
+    input: &Input,
+    splitter: Split,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    let mut solutions = solve_rec(input, &vec![], splitter, budget, "", monitor)?;
     let solutions = solutions.remove(&vec![]).unwrap();
     log!("That are {} solutions.", solutions.size());
     // log!("{}", &solutions);
-    solutions.build()
+    let num_solutions = usize::try_from(&solutions.size()).unwrap_or(usize::MAX);
+    budget.allocate(num_solutions.saturating_mul(size_of::<Solution>() + input.num_cells))?;
+    let mut solutions = solutions.build();
+    // Products don't know about runs without a sum that contain cells of both
+    // parts, so check them once the solutions are built.
+    solutions.retain(|solution| input.has_different_digits(solution));
+    Ok(solutions)
 }
 
 fn solve_rec(
     input: &Input,
     connecting_cells: &[usize],
+    splitter: Split,
+    budget: &MemoryBudget,
     log_prefix: &str,
-) -> HashMap<Vec<Value>, QuasiSolution> {
+    monitor: &Monitor,
+) -> Result<HashMap<Vec<Value>, QuasiSolution>, Error> {
+    monitor.visit()?;
     log!(
         "{}Solving input with {} cells, {} constraints, and {} connecting cells {:?} to pay attention to.",
         log_prefix,
@@ -224,26 +326,23 @@ fn solve_rec(
         connecting_cells.len(),
         connecting_cells,
     );
-    let split = split(input);
+    let split = splitter(input);
 
     if matches!(split, None) {
         log!("{}Solving with early abort.", log_prefix);
-        let solutions = super::sum_reachable_no_set::solve(input);
+        let mut solutions =
+            super::sum_reachable_no_set::solve(input, &monitor.without_recording())?;
+        solutions.retain(|solution| input.has_different_digits(solution));
         log!("{}Done. Found {} solutions.", log_prefix, solutions.len());
+        monitor.leaf(log_prefix.len() / 2, input.num_cells, solutions.len());
         let mut grouped = HashMap::<Vec<Value>, QuasiSolution>::new();
         for solution in solutions {
             let key = connecting_cells.iter().map(|i| solution[*i]).collect_vec();
-            grouped
-                .entry(key)
-                .and_modify(|existing_solution| {
-                    *existing_solution = QuasiSolution::Plus(vec![
-                        QuasiSolution::Concrete(solution.clone()),
-                        existing_solution.clone(),
-                    ]);
-                })
-                .or_insert(QuasiSolution::Concrete(solution));
+            let solution = QuasiSolution::Concrete(solution);
+            budget.allocate(solution.approximate_bytes())?;
+            insert_grouped(&mut grouped, key, solution, budget)?;
         }
-        return grouped;
+        return Ok(grouped);
     }
 
     let mut split = split.unwrap();
@@ -274,6 +373,13 @@ fn solve_rec(
         }
     }
 
+    monitor.split(
+        log_prefix.len() / 2,
+        red_mapping.len(),
+        blue_mapping.len(),
+        connections.len(),
+    );
+
     // Solve parts.
     let inner_log_prefix = format!("{}  ", log_prefix);
     let red_connecting_cells = connecting_cells
@@ -285,7 +391,14 @@ fn solve_rec(
         .filter(|it| colors[*it] == Color::Red)
         .map(|it| index_mapping[it])
         .collect_vec();
-    let red_solutions = solve_rec(&red, &red_connecting_cells, &inner_log_prefix);
+    let red_solutions = solve_rec(
+        &red,
+        &red_connecting_cells,
+        splitter,
+        budget,
+        &inner_log_prefix,
+        monitor,
+    )?;
     let blue_connecting_cells = connecting_cells
         .iter()
         .map(|it| *it)
@@ -295,24 +408,45 @@ fn solve_rec(
         .filter(|it| colors[*it] == Color::Blue)
         .map(|it| index_mapping[it])
         .collect_vec();
-    let blue_solutions = solve_rec(&blue, &blue_connecting_cells, &inner_log_prefix);
+    let blue_solutions = solve_rec(
+        &blue,
+        &blue_connecting_cells,
+        splitter,
+        budget,
+        &inner_log_prefix,
+        monitor,
+    )?;
 
     // Combine results.
     log!(
-        "{}Combining {}x{} solutions with {} connections.",
+        depth = log_prefix.len() / 2,
+        red = red_solutions.len(),
+        blue = blue_solutions.len(),
+        connections = connections.len();
+        "{}Combining solutions.",
         log_prefix,
-        red_solutions.len(),
-        blue_solutions.len(),
-        connections.len(),
     );
     log!(
         "{}Naively joining solutions would require checking {} candidates.",
         log_prefix,
         red_solutions.len() * blue_solutions.len()
     );
+    // The solutions of the parts are copied into each product, so their sizes
+    // are needed again and again.
+    let red_bytes = red_solutions
+        .values()
+        .map(|solution| solution.approximate_bytes())
+        .collect_vec();
+    let blue_bytes = blue_solutions
+        .values()
+        .map(|solution| solution.approximate_bytes())
+        .collect_vec();
     let mut solutions = vec![];
-    for (red_connecting_values, red_solution) in &red_solutions {
-        'solutions: for (blue_connecting_values, blue_solution) in &blue_solutions {
+    for ((red_connecting_values, red_solution), red_bytes) in red_solutions.iter().zip(&red_bytes) {
+        'solutions: for ((blue_connecting_values, blue_solution), blue_bytes) in
+            blue_solutions.iter().zip(&blue_bytes)
+        {
+            monitor.visit()?;
             for constraint in &connections {
                 let values = constraint
                     .cells
@@ -341,6 +475,7 @@ fn solve_rec(
                 }
             }
             log!(
+                Trace;
                 "{}  Combining red {:?} and blue {:?} works.",
                 log_prefix,
                 red_connecting_values,
@@ -372,21 +507,60 @@ fn solve_rec(
                 red: Box::new(red_solution.clone()),
                 blue: Box::new(blue_solution.clone()),
             };
+            budget.allocate(
+                approximate_key_bytes(&key)
+                    + size_of::<QuasiSolution>()
+                    + colors.len() * size_of::<Color>()
+                    + red_bytes
+                    + blue_bytes,
+            )?;
             solutions.push((key, value));
         }
     }
 
+    monitor.merged(
+        log_prefix.len() / 2,
+        red_solutions.len() * blue_solutions.len(),
+        solutions.len(),
+    );
+
     let mut grouped = HashMap::<Vec<Value>, QuasiSolution>::new();
     for (key, solution) in solutions {
-        grouped
-            .entry(key)
-            .and_modify(|existing_solution| {
-                *existing_solution =
-                    QuasiSolution::Plus(vec![solution.clone(), existing_solution.clone()]);
-            })
-            .or_insert(solution);
+        // The key was already accounted for when the solution was created.
+        budget.free(approximate_key_bytes(&key));
+        insert_grouped(&mut grouped, key, solution, budget)?;
+    }
+    // The maps of the parts are dropped.
+    for key in red_solutions.keys().chain(blue_solutions.keys()) {
+        budget.free(approximate_key_bytes(key));
     }
+    budget.free(red_bytes.into_iter().chain(blue_bytes).sum());
 
     log!("{}Done. Found some solutions.", log_prefix);
-    grouped
+    Ok(grouped)
+}
+
+fn insert_grouped(
+    grouped: &mut HashMap<Vec<Value>, QuasiSolution>,
+    key: Vec<Value>,
+    solution: QuasiSolution,
+    budget: &MemoryBudget,
+) -> Result<(), Error> {
+    match grouped.get_mut(&key) {
+        Some(existing_solution) => {
+            // Both solutions move into the new node.
+            budget.allocate(size_of::<QuasiSolution>())?;
+            *existing_solution = QuasiSolution::Plus(vec![solution, existing_solution.clone()]);
+        }
+        None => {
+            budget.allocate(approximate_key_bytes(&key))?;
+            grouped.insert(key, solution);
+        }
+    }
+    Ok(())
+}
+
+// The size of a key in a map of grouped solutions.
+fn approximate_key_bytes(key: &[Value]) -> usize {
+    size_of::<Vec<Value>>() + size_of_val(key)
 }
@@ -40,11 +40,40 @@
 //!   solutions to share their memory, reducing allocations.
 //! - fxhashmap: Like solution_in_rc, but change usages of `HashMap` to
 //!   `FxHashMap` from the `rustc-hash` crate.
+//! - partitioned: Like earlier_anchor, but instead of trying all cuts along
+//!   zero or one constraints and taking the first one that works, a graph
+//!   partitioner divides the Kakuro into two parts of similar size that are
+//!   connected by as few constraints as possible.
+//! - tree_decomposition: Computes a tree decomposition of the graph of runs,
+//!   where runs sharing a cell are connected. Then it does dynamic programming
+//!   over the bags of the decomposition, bottom up. For each bag, a table maps
+//!   the digits used so far in each of its runs to the number of ways to reach
+//!   that state. Solutions are constructed by walking the tables top down. The
+//!   runtime is exponential only in the width of the decomposition and the
+//!   number of solutions is known without constructing them.
+//! - parallel: Like no_alloc, but the top levels of the search tree are
+//!   expanded into many subtrees, which are then solved by several threads.
+//!   Results are concatenated in the order of the subtrees, so the output is
+//!   the same as the one of no_alloc.
 //!
 //! Ideas:
 //! - combine first by sum, only then by actual numbers
 //! - track the possibility wave like when solving by hand
 
+use crate::{
+    game::Value,
+    search_tree::{NodeKind, SearchTree},
+};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{self, Display, Formatter},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
 pub mod array_vec;
 pub mod better_vecs;
 pub mod connecting_cells;
@@ -56,6 +85,7 @@ pub mod iterative;
 pub mod lazy;
 pub mod naive;
 pub mod only_check_changes;
+pub mod parallel;
 pub mod prioritize;
 pub mod propagate_constraints;
 pub mod simpler_recursion_anchor;
@@ -63,5 +93,398 @@ pub mod solution_in_rc;
 pub mod sum_reachable;
 pub mod sum_reachable_no_set;
 pub mod sum_table;
+pub mod tree_decomposition;
 pub mod pass_empty_index;
 pub mod no_alloc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    OutOfBudget { max_bytes: usize },
+    Timeout { after: Duration },
+    Cancelled,
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OutOfBudget { max_bytes } => {
+                write!(f, "Exceeded the memory budget of {} bytes.", max_bytes)
+            }
+            Error::Timeout { after } => write!(f, "Timed out after {:?}.", after),
+            Error::Cancelled => write!(f, "Cancelled."),
+        }
+    }
+}
+
+/// Tracks the approximate memory used by the data structures of a solver.
+/// Solvers report bytes as they allocate and free them and get an error once
+/// the budget is exceeded. The numbers are estimates that ignore allocator
+/// overhead, so the actual memory use will be somewhat higher.
+pub struct MemoryBudget {
+    max_bytes: Option<usize>,
+    used_bytes: Cell<usize>,
+}
+impl MemoryBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes: Some(max_bytes),
+            used_bytes: Cell::new(0),
+        }
+    }
+    pub fn unlimited() -> Self {
+        Self {
+            max_bytes: None,
+            used_bytes: Cell::new(0),
+        }
+    }
+
+    pub fn allocate(&self, bytes: usize) -> Result<(), Error> {
+        let used_bytes = self.used_bytes.get() + bytes;
+        self.used_bytes.set(used_bytes);
+        match self.max_bytes {
+            Some(max_bytes) if used_bytes > max_bytes => Err(Error::OutOfBudget { max_bytes }),
+            _ => Ok(()),
+        }
+    }
+    pub fn free(&self, bytes: usize) {
+        self.used_bytes
+            .set(self.used_bytes.get().saturating_sub(bytes));
+    }
+}
+
+/// Can be shared with a running solver to cancel it from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Looking at the clock takes longer than visiting a node in most solvers, so
+// the monitor only checks every this many nodes.
+const NODES_PER_CHECK: u32 = 1024;
+
+/// A snapshot of how far a solver got.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub nodes_visited: u64,
+    pub solutions_found: u64,
+    /// How many choices deep the search currently is.
+    pub depth: usize,
+    /// Estimate of how much of the search space was already explored, between
+    /// 0 and 1. `None` if the solver doesn't report its choices.
+    pub fraction: Option<f64>,
+    pub elapsed: Duration,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+struct Reporter {
+    callback: ProgressCallback,
+    interval: Duration,
+    last_report: Arc<Mutex<Instant>>,
+}
+
+/// Every solver gets a monitor and reports each node of its search to it. The
+/// monitor tells the solver to stop if the search was cancelled or the
+/// deadline has passed.
+///
+/// Solvers can also tell the monitor which choice they are trying and when
+/// they found a solution. If a progress callback is registered, the monitor
+/// regularly reports that to it. If a search tree is registered, the monitor
+/// records the choices, failures and splits of the solver in it.
+///
+/// Cloning a monitor gives one with the same token, deadline, counters,
+/// callback and search tree that can be moved to another thread.
+pub struct Monitor {
+    token: CancellationToken,
+    start: Instant,
+    deadline: Option<Instant>,
+    nodes_until_check: Cell<u32>,
+    nodes_visited: Arc<AtomicU64>,
+    solutions_found: Arc<AtomicU64>,
+    reporter: Option<Reporter>,
+    // For each depth, the index of the current choice and the number of
+    // choices. Only tracked if there's a reporter.
+    path: RefCell<Vec<(usize, usize)>>,
+    recording: Option<Arc<Mutex<SearchTree>>>,
+    // For each depth, the recorded node that the search is currently in. `None`
+    // if the search tree was full.
+    recorded_path: RefCell<Vec<Option<usize>>>,
+}
+impl Monitor {
+    pub fn new(token: CancellationToken, timeout: Option<Duration>) -> Self {
+        let start = Instant::now();
+        Self {
+            token,
+            start,
+            deadline: timeout.map(|timeout| start + timeout),
+            nodes_until_check: Cell::new(NODES_PER_CHECK),
+            nodes_visited: Default::default(),
+            solutions_found: Default::default(),
+            reporter: None,
+            path: Default::default(),
+            recording: None,
+            recorded_path: Default::default(),
+        }
+    }
+    pub fn unlimited() -> Self {
+        Self::new(CancellationToken::new(), None)
+    }
+
+    /// Calls the callback with the current progress at most once per
+    /// interval.
+    pub fn with_progress(mut self, interval: Duration, callback: ProgressCallback) -> Self {
+        self.reporter = Some(Reporter {
+            callback,
+            interval,
+            last_report: Arc::new(Mutex::new(self.start)),
+        });
+        self
+    }
+
+    /// Records the search into the given tree.
+    pub fn with_recording(mut self, tree: Arc<Mutex<SearchTree>>) -> Self {
+        self.recording = Some(tree);
+        self
+    }
+
+    /// A monitor sharing everything except the recording. Solvers use it for
+    /// nested solvers whose search tree would be too detailed.
+    pub fn without_recording(&self) -> Self {
+        let mut monitor = self.clone();
+        monitor.recording = None;
+        monitor
+    }
+
+    /// Makes this and all other monitors sharing the token fail their next
+    /// check.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn visit(&self) -> Result<(), Error> {
+        let nodes_until_check = self.nodes_until_check.get() - 1;
+        if nodes_until_check > 0 {
+            self.nodes_until_check.set(nodes_until_check);
+            return Ok(());
+        }
+        self.nodes_until_check.set(NODES_PER_CHECK);
+        self.nodes_visited
+            .fetch_add(NODES_PER_CHECK as u64, Ordering::Relaxed);
+        self.check()?;
+        self.report_if_due();
+        Ok(())
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        if self.token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() > deadline => Err(Error::Timeout {
+                after: deadline - self.start,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Tells the monitor that the search tries the `choice`th of
+    /// `num_choices` alternatives at the given depth. Choices at deeper levels
+    /// are forgotten.
+    pub fn choose(&self, depth: usize, choice: usize, num_choices: usize) {
+        if self.reporter.is_none() {
+            return;
+        }
+        let mut path = self.path.borrow_mut();
+        path.resize(depth, (0, 1));
+        path.push((choice, num_choices));
+    }
+
+    /// Tells the monitor that the search fills the cell with the digit at the
+    /// given depth.
+    pub fn assign(&self, depth: usize, cell: usize, digit: Value) {
+        self.choose(depth, digit as usize - 1, 9);
+        self.record(
+            depth,
+            NodeKind::Assign {
+                cell,
+                digit,
+                failed: false,
+                violated: None,
+                solution: false,
+            },
+        );
+    }
+
+    /// Tells the monitor that the last assignment made the Kakuro invalid.
+    pub fn fail(&self, violated_constraint: Option<usize>) {
+        self.update_recorded(None, |kind| {
+            if let NodeKind::Assign {
+                failed, violated, ..
+            } = kind
+            {
+                *failed = true;
+                *violated = violated_constraint;
+            }
+        });
+    }
+
+    pub fn found_solution(&self) {
+        self.solutions_found.fetch_add(1, Ordering::Relaxed);
+        self.update_recorded(None, |kind| {
+            if let NodeKind::Assign { solution, .. } = kind {
+                *solution = true;
+            }
+        });
+    }
+
+    /// Tells the monitor that a divide solver split the Kakuro at the given
+    /// depth.
+    pub fn split(&self, depth: usize, red_cells: usize, blue_cells: usize, connections: usize) {
+        self.record(
+            depth,
+            NodeKind::Split {
+                red_cells,
+                blue_cells,
+                connections,
+                candidates: None,
+                merged: None,
+            },
+        );
+    }
+
+    /// Tells the monitor how many combinations of red and blue solutions the
+    /// split at the given depth checked and how many of them fit together.
+    pub fn merged(&self, depth: usize, num_candidates: usize, num_merged: usize) {
+        self.update_recorded(Some(depth), |kind| {
+            if let NodeKind::Split {
+                candidates, merged, ..
+            } = kind
+            {
+                *candidates = Some(num_candidates);
+                *merged = Some(num_merged);
+            }
+        });
+    }
+
+    /// Tells the monitor that a divide solver solved a part at the given depth
+    /// without splitting it further.
+    pub fn leaf(&self, depth: usize, cells: usize, solutions: usize) {
+        self.record(depth, NodeKind::Leaf { cells, solutions });
+    }
+
+    fn record(&self, depth: usize, kind: NodeKind) {
+        let tree = match &self.recording {
+            Some(tree) => tree,
+            None => return,
+        };
+        let mut recorded_path = self.recorded_path.borrow_mut();
+        recorded_path.resize(depth, None);
+        let parent = recorded_path.last().copied().flatten();
+        let node = tree.lock().unwrap().add(parent, depth, kind);
+        recorded_path.push(node);
+    }
+
+    // Changes the recorded node at the given depth, or the deepest one.
+    fn update_recorded(&self, depth: Option<usize>, update: impl FnOnce(&mut NodeKind)) {
+        let tree = match &self.recording {
+            Some(tree) => tree,
+            None => return,
+        };
+        let recorded_path = self.recorded_path.borrow();
+        let node = match depth {
+            Some(depth) => recorded_path.get(depth).copied().flatten(),
+            None => recorded_path.last().copied().flatten(),
+        };
+        if let Some(node) = node {
+            update(&mut tree.lock().unwrap().nodes[node].kind);
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let path = self.path.borrow();
+        // Assumes that all subtrees at a level are equally big. Then the
+        // choices before the current one at each level are done.
+        let mut fraction = 0.0;
+        let mut subtree_size = 1.0;
+        for (choice, num_choices) in path.iter() {
+            subtree_size /= *num_choices as f64;
+            fraction += *choice as f64 * subtree_size;
+        }
+        let nodes_pending = NODES_PER_CHECK - self.nodes_until_check.get();
+        Progress {
+            nodes_visited: self.nodes_visited.load(Ordering::Relaxed) + nodes_pending as u64,
+            solutions_found: self.solutions_found.load(Ordering::Relaxed),
+            depth: path.len(),
+            fraction: if path.is_empty() {
+                None
+            } else {
+                Some(fraction)
+            },
+            elapsed: self.start.elapsed(),
+        }
+    }
+
+    /// Reports the current progress to the callback, regardless of when the
+    /// last report happened.
+    pub fn report(&self) {
+        if let Some(reporter) = &self.reporter {
+            *reporter.last_report.lock().unwrap() = Instant::now();
+            (reporter.callback)(&self.progress());
+        }
+    }
+
+    fn report_if_due(&self) {
+        let reporter = match &self.reporter {
+            Some(reporter) => reporter,
+            None => return,
+        };
+        // Other threads may be reporting right now, so don't wait for them.
+        let mut last_report = match reporter.last_report.try_lock() {
+            Ok(last_report) => last_report,
+            Err(_) => return,
+        };
+        if last_report.elapsed() < reporter.interval {
+            return;
+        }
+        *last_report = Instant::now();
+        drop(last_report);
+        (reporter.callback)(&self.progress());
+    }
+}
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        // Monitors for nested solvers or other threads share the counters, so
+        // they shouldn't lose the nodes visited since their last check.
+        let nodes_pending = NODES_PER_CHECK - self.nodes_until_check.get();
+        self.nodes_visited
+            .fetch_add(nodes_pending as u64, Ordering::Relaxed);
+    }
+}
+impl Clone for Monitor {
+    fn clone(&self) -> Self {
+        Self {
+            token: self.token.clone(),
+            start: self.start,
+            deadline: self.deadline,
+            nodes_until_check: Cell::new(NODES_PER_CHECK),
+            nodes_visited: self.nodes_visited.clone(),
+            solutions_found: self.solutions_found.clone(),
+            reporter: self.reporter.as_ref().map(|reporter| Reporter {
+                callback: reporter.callback.clone(),
+                interval: reporter.interval,
+                last_report: reporter.last_report.clone(),
+            }),
+            path: RefCell::new(self.path.borrow().clone()),
+            recording: self.recording.clone(),
+            recorded_path: RefCell::new(self.recorded_path.borrow().clone()),
+        }
+    }
+}
@@ -1,21 +1,28 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Input, Output},
     log,
 };
 use itertools::Itertools;
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt = vec![1; input.num_cells];
     let mut solutions = vec![];
+    for i in 0..attempt.len() {
+        monitor.choose(i, 0, 9);
+    }
 
     'search: loop {
+        monitor.visit()?;
         log!(
+            Trace;
             "Evaluating attempt {}",
             attempt.iter().map(|digit| format!("{}", digit)).join("")
         );
 
         if input.is_solution(&attempt) {
             solutions.push(attempt.clone());
+            monitor.found_solution();
         }
 
         // Increase attempt by one, interpreted as a single number.
@@ -29,10 +36,11 @@ pub fn solve(input: &Input) -> Output {
                 }
                 i -= 1;
             } else {
+                monitor.choose(i, attempt[i] as usize - 1, 9);
                 break;
             }
         }
     }
 
-    solutions
+    Ok(solutions)
 }
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
     log,
@@ -31,7 +32,7 @@ macro_rules! check_additional {
 }
 
 #[extension_trait]
-impl ConstraintExt5 for Constraint {
+pub(super) impl ConstraintExt5 for Constraint {
     fn is_satisfied_by(&self, attempt: &Vec<Option<Value>>) -> bool {
         let mut seen = [false; 9];
         let mut sum = 0usize;
@@ -71,33 +72,66 @@ impl ConstraintExt5 for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
-    let mut attempt = vec![None; input.num_cells];
-    let mut solutions = vec![];
+/// For each cell, the constraints and the runs without a sum that contain it.
+pub(super) fn affected_runs(input: &Input) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
     let mut affected_constraints = vec![vec![]; input.num_cells];
     for (i, constraint) in input.constraints.iter().enumerate() {
         for cell in &constraint.cells {
             affected_constraints[*cell].push(i);
         }
     }
+    let mut affected_all_different = vec![vec![]; input.num_cells];
+    for (i, cells) in input.all_different.iter().enumerate() {
+        for cell in cells {
+            affected_all_different[*cell].push(i);
+        }
+    }
+    (affected_constraints, affected_all_different)
+}
+
+/// Whether another cell of the given runs without a sum has the same digit as
+/// the cell.
+pub(super) fn repeats_digit(
+    input: &Input,
+    all_different: &[usize],
+    cell: usize,
+    attempt: &[Option<Value>],
+) -> bool {
+    all_different.iter().any(|run| {
+        input.all_different[*run]
+            .iter()
+            .any(|other| *other != cell && attempt[*other] == attempt[cell])
+    })
+}
+
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
+    let mut attempt = vec![None; input.num_cells];
+    let mut solutions = vec![];
+    let (affected_constraints, affected_all_different) = affected_runs(input);
     solve_rec(
         input,
         &affected_constraints,
+        &affected_all_different,
         0,
         &mut attempt,
         &mut solutions,
-    );
-    solutions
+        monitor,
+    )?;
+    Ok(solutions)
 }
 
-fn solve_rec(
+pub(super) fn solve_rec(
     input: &Input,
     affected_constraints: &[Vec<usize>],
+    affected_all_different: &[Vec<usize>],
     first_empty: usize,
     attempt: &mut Vec<Option<Value>>,
     solutions: &mut Vec<Solution>,
-) {
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
     log!(
+        Trace, depth = first_empty;
         "Evaluating attempt {}",
         attempt
             .iter()
@@ -112,24 +146,34 @@ fn solve_rec(
 
     if first_empty < attempt.len() {
         'candidates: for i in 1..=9 {
+            monitor.assign(first_empty, first_empty, i);
             attempt[first_empty] = Some(i);
 
             for constraint_index in &affected_constraints[first_empty] {
                 let constraint = &input.constraints[*constraint_index];
                 if !constraint.is_satisfied_by(attempt) {
+                    monitor.fail(Some(*constraint_index));
                     continue 'candidates;
                 }
             }
+            if repeats_digit(input, &affected_all_different[first_empty], first_empty, attempt) {
+                monitor.fail(None);
+                continue 'candidates;
+            }
             solve_rec(
                 input,
                 affected_constraints,
+                affected_all_different,
                 first_empty + 1,
                 attempt,
                 solutions,
-            );
+                monitor,
+            )?;
         }
         attempt[first_empty] = None;
     } else {
         solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
     log,
@@ -37,7 +38,7 @@ impl ConstraintExt4 for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt = vec![None; input.num_cells];
     let mut solutions = vec![];
     let mut affected_constraints = vec![vec![]; input.num_cells];
@@ -46,8 +47,14 @@ pub fn solve(input: &Input) -> Output {
             affected_constraints[*cell].push(i);
         }
     }
-    solve_rec(input, &affected_constraints, &mut attempt, &mut solutions);
-    solutions
+    solve_rec(
+        input,
+        &affected_constraints,
+        &mut attempt,
+        &mut solutions,
+        monitor,
+    )?;
+    Ok(solutions)
 }
 
 fn solve_rec(
@@ -55,8 +62,11 @@ fn solve_rec(
     affected_constraints: &[Vec<usize>],
     attempt: &mut Vec<Option<Value>>,
     solutions: &mut Vec<Solution>,
-) {
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
     log!(
+        Trace;
         "Evaluating attempt {}",
         attempt
             .iter()
@@ -72,17 +82,21 @@ fn solve_rec(
     let first_empty_cell_index = attempt.iter().position(|it| it.is_none());
     if let Some(index) = first_empty_cell_index {
         'candidates: for i in 1..=9 {
+            monitor.assign(index, index, i);
             attempt[index] = Some(i);
             for constraint_index in &affected_constraints[index] {
                 let constraint = &input.constraints[*constraint_index];
                 if !constraint.is_satisfied_by(attempt) {
+                    monitor.fail(Some(*constraint_index));
                     continue 'candidates;
                 }
             }
-            solve_rec(input, affected_constraints, attempt, solutions);
+            solve_rec(input, affected_constraints, attempt, solutions, monitor)?;
         }
         attempt[index] = None;
     } else {
         solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
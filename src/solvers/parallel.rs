@@ -0,0 +1,112 @@
+use super::{
+    no_alloc::{self, ConstraintExt5},
+    Error, Monitor,
+};
+use crate::{
+    game::{Input, Output, Solution, Value},
+    log,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+// How many subtrees each thread should get on average. Subtrees differ wildly
+// in size, so having more of them than threads lets threads that finish early
+// pick up more work instead of idling.
+const SUBTREES_PER_THREAD: usize = 16;
+
+pub fn solve(input: &Input, num_threads: usize, monitor: &Monitor) -> Result<Output, Error> {
+    let num_threads = num_threads.max(1);
+
+    let (affected_constraints, affected_all_different) = no_alloc::affected_runs(input);
+
+    // Expand the top levels of the search tree breadth-first until there are
+    // enough subtrees to keep all threads busy. Candidates are expanded in the
+    // same order as the sequential solver visits them, so concatenating the
+    // subtree results in order gives the same output.
+    let mut subtrees = vec![vec![None; input.num_cells]];
+    let mut first_empty = 0;
+    while first_empty < input.num_cells
+        && !subtrees.is_empty()
+        && subtrees.len() < num_threads * SUBTREES_PER_THREAD
+    {
+        let mut expanded = vec![];
+        for attempt in subtrees {
+            'candidates: for i in 1..=9 {
+                let mut attempt = attempt.clone();
+                attempt[first_empty] = Some(i);
+                for constraint_index in &affected_constraints[first_empty] {
+                    let constraint = &input.constraints[*constraint_index];
+                    if !constraint.is_satisfied_by(&attempt) {
+                        continue 'candidates;
+                    }
+                }
+                let all_different = &affected_all_different[first_empty];
+                if no_alloc::repeats_digit(input, all_different, first_empty, &attempt) {
+                    continue 'candidates;
+                }
+                expanded.push(attempt);
+            }
+        }
+        subtrees = expanded;
+        first_empty += 1;
+    }
+    log!(
+        Info, subtrees = subtrees.len(), depth = first_empty;
+        "Split the search into subtrees."
+    );
+
+    // Threads take the next unsolved subtree from a shared counter.
+    let next_subtree = AtomicUsize::new(0);
+    let results: Mutex<Vec<(usize, Vec<Solution>)>> = Mutex::new(vec![]);
+    let error: Mutex<Option<Error>> = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..num_threads.min(subtrees.len()) {
+            let monitor = monitor.clone();
+            let (next_subtree, subtrees, results, error) =
+                (&next_subtree, &subtrees, &results, &error);
+            let (affected_constraints, affected_all_different) =
+                (&affected_constraints, &affected_all_different);
+            scope.spawn(move || loop {
+                let index = next_subtree.fetch_add(1, Ordering::Relaxed);
+                if index >= subtrees.len() {
+                    break;
+                }
+                monitor.choose(0, index, subtrees.len());
+                let mut attempt: Vec<Option<Value>> = subtrees[index].clone();
+                let mut solutions = vec![];
+                let result = no_alloc::solve_rec(
+                    input,
+                    affected_constraints,
+                    affected_all_different,
+                    first_empty,
+                    &mut attempt,
+                    &mut solutions,
+                    &monitor,
+                );
+                if let Err(err) = result {
+                    // The first error wins. Stop the other threads, which then
+                    // fail with a cancellation.
+                    error.lock().unwrap().get_or_insert(err);
+                    monitor.cancel();
+                    break;
+                }
+                results.lock().unwrap().push((index, solutions));
+            });
+        }
+    });
+    if let Some(error) = error.into_inner().unwrap() {
+        return Err(error);
+    }
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results
+        .into_iter()
+        .flat_map(|(_, solutions)| solutions)
+        .collect())
+}
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
     log,
@@ -37,7 +38,7 @@ impl ConstraintExt5 for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt = vec![None; input.num_cells];
     let mut solutions = vec![];
     let mut affected_constraints = vec![vec![]; input.num_cells];
@@ -46,8 +47,15 @@ pub fn solve(input: &Input) -> Output {
             affected_constraints[*cell].push(i);
         }
     }
-    solve_rec(input, &affected_constraints, 0, &mut attempt, &mut solutions);
-    solutions
+    solve_rec(
+        input,
+        &affected_constraints,
+        0,
+        &mut attempt,
+        &mut solutions,
+        monitor,
+    )?;
+    Ok(solutions)
 }
 
 fn solve_rec(
@@ -56,8 +64,11 @@ fn solve_rec(
     first_empty: usize,
     attempt: &mut Vec<Option<Value>>,
     solutions: &mut Vec<Solution>,
-) {
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
     log!(
+        Trace, depth = first_empty;
         "Evaluating attempt {}",
         attempt
             .iter()
@@ -72,17 +83,33 @@ fn solve_rec(
 
     if first_empty < attempt.len() {
         'candidates: for i in 1..=9 {
+            monitor.assign(first_empty, first_empty, i);
             attempt[first_empty] = Some(i);
             for constraint_index in &affected_constraints[first_empty] {
                 let constraint = &input.constraints[*constraint_index];
                 if !constraint.is_satisfied_by(attempt) {
+                    monitor.fail(Some(*constraint_index));
+                    log!(
+                        Trace, cell = first_empty, constraint = *constraint_index;
+                        "Digit {} violates a constraint.",
+                        i
+                    );
                     continue 'candidates;
                 }
             }
-            solve_rec(input, affected_constraints, first_empty + 1, attempt, solutions);
+            solve_rec(
+                input,
+                affected_constraints,
+                first_empty + 1,
+                attempt,
+                solutions,
+                monitor,
+            )?;
         }
         attempt[first_empty] = None;
     } else {
         solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Input, Output, Solution, Value},
     log,
@@ -46,15 +47,22 @@ impl InputExt for Input {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     let mut attempt = vec![None; input.num_cells];
     let mut solutions = vec![];
-    solve_rec(input, &mut attempt, &mut solutions);
-    solutions
+    solve_rec(input, &mut attempt, &mut solutions, monitor)?;
+    Ok(solutions)
 }
 
-fn solve_rec(input: &Input, attempt: &mut Game, solutions: &mut Vec<Solution>) {
+fn solve_rec(
+    input: &Input,
+    attempt: &mut Game,
+    solutions: &mut Vec<Solution>,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
     log!(
+        Trace;
         "Evaluating attempt {}",
         attempt
             .iter()
@@ -68,7 +76,8 @@ fn solve_rec(input: &Input, attempt: &mut Game, solutions: &mut Vec<Solution>) {
     );
 
     if !input.is_possible_solution(attempt) {
-        return;
+        monitor.fail(None);
+        return Ok(());
     }
 
     // For each cell, save how many partially filled constraints contain it.
@@ -104,13 +113,17 @@ fn solve_rec(input: &Input, attempt: &mut Game, solutions: &mut Vec<Solution>) {
         });
 
     if let Some(cell) = cell_to_fill {
+        let depth = attempt.iter().filter(|it| it.is_some()).count();
         for i in 1..=9 {
+            monitor.assign(depth, cell, i);
             attempt[cell] = Some(i);
-            solve_rec(input, attempt, solutions);
+            solve_rec(input, attempt, solutions, monitor)?;
         }
         attempt[cell] = None;
     } else {
         // This is a solution.
         solutions.push(attempt.iter().map(|it| it.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
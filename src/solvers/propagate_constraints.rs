@@ -1,12 +1,14 @@
+use super::{Error, MemoryBudget, Monitor};
 use crate::{
     game::{self, Output, Solution, Value},
-    log,
+    log, partition,
 };
 use itertools::Itertools;
 use std::{
     cmp::max,
     collections::HashMap,
     fmt::{self, Display},
+    mem::{size_of, size_of_val},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -101,63 +103,97 @@ fn split(num_cells: usize, constraints: &[Constraint]) -> Option<SplitInput> {
                 continue;
             }
 
-            // A vector that maps cell indizes from the original input to the
-            // cell indizes in the smaller parts.
-            let index_mapping = {
-                let mut red_counter = 0;
-                let mut blue_counter = 0;
-                let mut mapping = vec![];
-                for i in 0..num_cells {
-                    match colors[i] {
-                        Color::Red => {
-                            mapping.push(red_counter);
-                            red_counter += 1;
-                        }
-                        Color::Blue => {
-                            mapping.push(blue_counter);
-                            blue_counter += 1;
-                        }
-                    }
-                }
-                mapping
-            };
+            return Some(split_by_colors(
+                colors,
+                &connecting_constraints,
+                &remaining_constraints,
+            ));
+        }
+    }
+    None
+}
 
-            fn constraints_for_color(
-                color: Color,
-                constraints: &[Constraint],
-                colors: &[Color],
-                mapping: &[usize],
-            ) -> Vec<Constraint> {
-                constraints
-                    .iter()
-                    .map(|constraint| translate_constraint(constraint, color, &colors, &mapping))
-                    .filter(|constraint| !constraint.cells.is_empty())
-                    .collect()
+// Like split, but uses the graph partitioner, which balances both parts and
+// minimizes the number of connecting constraints instead of trying cuts along
+// as few constraints as possible.
+fn split_partitioned(num_cells: usize, constraints: &[Constraint]) -> Option<SplitInput> {
+    let cells = constraints
+        .iter()
+        .map(|constraint| &constraint.cells[..])
+        .collect_vec();
+    let colors = partition::bisect(num_cells, &cells, 1)?
+        .into_iter()
+        .map(|in_first| if in_first { Color::Red } else { Color::Blue })
+        .collect_vec();
+    let (connecting_constraints, remaining_constraints): (Vec<_>, Vec<_>) =
+        constraints.iter().cloned().partition(|constraint| {
+            let mut cell_colors = constraint.cells.iter().map(|cell| colors[*cell]);
+            let first_color = cell_colors.next();
+            cell_colors.any(|color| Some(color) != first_color)
+        });
+    Some(split_by_colors(
+        colors,
+        &connecting_constraints,
+        &remaining_constraints,
+    ))
+}
+
+fn split_by_colors(
+    colors: Vec<Color>,
+    connecting_constraints: &[Constraint],
+    remaining_constraints: &[Constraint],
+) -> SplitInput {
+    // A vector that maps cell indizes from the original input to the
+    // cell indizes in the smaller parts.
+    let index_mapping = {
+        let mut red_counter = 0;
+        let mut blue_counter = 0;
+        let mut mapping = vec![];
+        for color in &colors {
+            match color {
+                Color::Red => {
+                    mapping.push(red_counter);
+                    red_counter += 1;
+                }
+                Color::Blue => {
+                    mapping.push(blue_counter);
+                    blue_counter += 1;
+                }
             }
-            let all_constraints = add_slices(&connecting_constraints, &remaining_constraints);
-            return Some(SplitInput {
-                colors: colors.clone(),
-                index_mapping: index_mapping.clone(),
-                red_constraints: constraints_for_color(
-                    Color::Red,
-                    &all_constraints,
-                    &colors,
-                    &index_mapping,
-                ),
-                blue_constraints: constraints_for_color(
-                    Color::Blue,
-                    &all_constraints,
-                    &colors,
-                    &index_mapping,
-                ),
-                connections: connecting_constraints
-                    .iter()
-                    .map(|it| (*it).clone())
-                    .collect(),
-            });
         }
+        mapping
+    };
+
+    fn constraints_for_color(
+        color: Color,
+        constraints: &[Constraint],
+        colors: &[Color],
+        mapping: &[usize],
+    ) -> Vec<Constraint> {
+        constraints
+            .iter()
+            .map(|constraint| translate_constraint(constraint, color, &colors, &mapping))
+            .filter(|constraint| !constraint.cells.is_empty())
+            .collect()
+    }
+    let all_constraints = add_slices(connecting_constraints, remaining_constraints);
+    SplitInput {
+        red_constraints: constraints_for_color(
+            Color::Red,
+            &all_constraints,
+            &colors,
+            &index_mapping,
+        ),
+        blue_constraints: constraints_for_color(
+            Color::Blue,
+            &all_constraints,
+            &colors,
+            &index_mapping,
+        ),
+        colors,
+        index_mapping,
+        connections: connecting_constraints.to_vec(),
     }
-    None
 }
 
 #[derive(Debug, Clone)]
@@ -171,6 +207,22 @@ enum QuasiSolution {
     },
 }
 impl QuasiSolution {
+    // The size of this solution including all of its children, which aren't
+    // shared but copied into each product.
+    fn approximate_bytes(&self) -> usize {
+        size_of::<QuasiSolution>()
+            + match self {
+                QuasiSolution::Concrete(concrete) => concrete.len() * size_of::<Value>(),
+                QuasiSolution::Plus(children) => {
+                    children.iter().map(|it| it.approximate_bytes()).sum()
+                }
+                QuasiSolution::Product { colors, red, blue } => {
+                    colors.len() * size_of::<Color>()
+                        + red.approximate_bytes()
+                        + blue.approximate_bytes()
+                }
+            }
+    }
     fn size(&self) -> usize {
         match self {
             QuasiSolution::Concrete(_) => 1,
@@ -192,7 +244,7 @@ impl QuasiSolution {
             QuasiSolution::Product { colors, red, blue } => {
                 let red = red.simplify();
                 let blue = blue.simplify();
-                if red.size() == 1 || blue.size() == 1 {
+                if red.size() == 1 && blue.size() == 1 {
                     let mut red = red.build().pop().unwrap();
                     let mut blue = blue.build().pop().unwrap();
                     let mut solution = vec![];
@@ -430,17 +482,54 @@ fn translate_constraint(
     }
 }
 
-pub fn solve(input: &game::Input) -> Output {
+// Runs without a sum become constraints that allow any sum of different
+// digits.
+fn constraints(input: &game::Input) -> Vec<Constraint> {
+    input
+        .constraints
+        .iter()
+        .map(|it| it.clone().into())
+        .chain(input.all_different.iter().map(|cells| Constraint {
+            cells: cells.iter().copied().collect(),
+            min: MINS[cells.len()],
+            max: MAXES[cells.len()],
+        }))
+        .collect()
+}
+
+type Split = fn(usize, &[Constraint]) -> Option<SplitInput>;
+
+pub fn solve_within(
+    input: &game::Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split, budget, monitor)
+}
+
+pub fn solve_partitioned_within(
+    input: &game::Input,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
+    solve_with_splitter(input, split_partitioned, budget, monitor)
+}
+
+fn solve_with_splitter(
+    input: &game::Input,
+    splitter: Split,
+    budget: &MemoryBudget,
+    monitor: &Monitor,
+) -> Result<Output, Error> {
     let mut solutions = solve_rec(
         input.num_cells,
-        &input
-            .constraints
-            .iter()
-            .map(|it| it.clone().into())
-            .collect_vec(),
+        &constraints(input),
         &[],
+        splitter,
+        budget,
         "",
-    );
+        monitor,
+    )?;
     let solutions = solutions.remove(&vec![]).unwrap();
     log!("Solutions:\n{}", solutions);
     log!("There are {} solutions.", solutions.size());
@@ -448,7 +537,8 @@ pub fn solve(input: &game::Input) -> Output {
     let solutions = solutions.simplify();
     log!("There are {} simple solutions.", solutions.size());
     log!("{}", &solutions);
-    solutions.build()
+    budget.allocate(solutions.size() * (size_of::<Solution>() + input.num_cells))?;
+    Ok(solutions.build())
 }
 
 // Takes a number of cells and all constraints. The additional information of
@@ -460,8 +550,12 @@ fn solve_rec(
     num_cells: usize,
     all_constraints: &[Constraint],
     connecting_constraints: &[Constraint],
+    splitter: Split,
+    budget: &MemoryBudget,
     log_prefix: &str,
-) -> HashMap<Vec<Vec<Value>>, QuasiSolution> {
+    monitor: &Monitor,
+) -> Result<HashMap<Vec<Vec<Value>>, QuasiSolution>, Error> {
+    monitor.visit()?;
     log!(
         "{}Solving input with {} cells and {} constraints to pay attention to: {:?}",
         log_prefix,
@@ -469,12 +563,13 @@ fn solve_rec(
         all_constraints.len(),
         all_constraints,
     );
-    let split = split(num_cells, all_constraints);
+    let split = splitter(num_cells, all_constraints);
 
     if split.is_none() {
         log!("{}Solving with simple algorithm.", log_prefix);
         let solutions = early_abort::solve(num_cells, all_constraints);
         log!("{}Done. Found {} solutions.", log_prefix, solutions.len());
+        monitor.leaf(log_prefix.len() / 2, num_cells, solutions.len());
         let mut grouped = HashMap::<Vec<Vec<Value>>, QuasiSolution>::new();
         for solution in solutions {
             let key = connecting_constraints
@@ -485,17 +580,11 @@ fn solve_rec(
                     cells
                 })
                 .collect_vec();
-            grouped
-                .entry(key)
-                .and_modify(|existing_solution| {
-                    *existing_solution = QuasiSolution::Plus(vec![
-                        QuasiSolution::Concrete(solution.clone()),
-                        existing_solution.clone(),
-                    ]);
-                })
-                .or_insert(QuasiSolution::Concrete(solution));
+            let solution = QuasiSolution::Concrete(solution);
+            budget.allocate(solution.approximate_bytes())?;
+            insert_grouped(&mut grouped, key, solution, budget)?;
         }
-        return grouped;
+        return Ok(grouped);
     }
 
     let mut split = split.unwrap();
@@ -528,6 +617,13 @@ fn solve_rec(
         }
     }
 
+    monitor.split(
+        log_prefix.len() / 2,
+        red_mapping.len(),
+        blue_mapping.len(),
+        split_connecting_constraints.len(),
+    );
+
     // Solve parts.
     let inner_log_prefix = format!("{}  ", log_prefix);
     let red_solutions = solve_rec(
@@ -539,8 +635,11 @@ fn solve_rec(
                 translate_constraint(&constraint, Color::Red, &colors, &index_mapping)
             })
             .collect_vec(),
+        splitter,
+        budget,
         &inner_log_prefix,
-    );
+        monitor,
+    )?;
     let blue_solutions = solve_rec(
         blue_mapping.len(),
         &blue_constraints,
@@ -550,25 +649,42 @@ fn solve_rec(
                 translate_constraint(&constraint, Color::Blue, &colors, &index_mapping)
             })
             .collect_vec(),
+        splitter,
+        budget,
         &inner_log_prefix,
-    );
+        monitor,
+    )?;
 
     // Combine results.
     log!(
-        "{}Combining {}x{} solutions with {} connections.",
+        depth = log_prefix.len() / 2,
+        red = red_solutions.len(),
+        blue = blue_solutions.len(),
+        connections = split_connecting_constraints.len();
+        "{}Combining solutions.",
         log_prefix,
-        red_solutions.len(),
-        blue_solutions.len(),
-        split_connecting_constraints.len(),
     );
     log!(
         "{}Naively joining solutions would require checking {} candidates.",
         log_prefix,
         red_solutions.len() * blue_solutions.len()
     );
+    // The solutions of the parts are copied into each product, so their sizes
+    // are needed again and again.
+    let red_bytes = red_solutions
+        .values()
+        .map(|solution| solution.approximate_bytes())
+        .collect_vec();
+    let blue_bytes = blue_solutions
+        .values()
+        .map(|solution| solution.approximate_bytes())
+        .collect_vec();
     let mut solutions = vec![];
-    for (red_connecting_values, red_solution) in &red_solutions {
-        'solutions: for (blue_connecting_values, blue_solution) in &blue_solutions {
+    for ((red_connecting_values, red_solution), red_bytes) in red_solutions.iter().zip(&red_bytes) {
+        'solutions: for ((blue_connecting_values, blue_solution), blue_bytes) in
+            blue_solutions.iter().zip(&blue_bytes)
+        {
+            monitor.visit()?;
             for ((constraint, red_values), blue_values) in split_connecting_constraints
                 .iter()
                 .zip(
@@ -588,6 +704,7 @@ fn solve_rec(
                 }
             }
             log!(
+                Trace;
                 "{}  Combining red {:?} and blue {:?} works.",
                 log_prefix,
                 red_connecting_values,
@@ -604,20 +721,34 @@ fn solve_rec(
                 red: Box::new(red_solution.clone()),
                 blue: Box::new(blue_solution.clone()),
             };
+            budget.allocate(
+                approximate_key_bytes(&key)
+                    + size_of::<QuasiSolution>()
+                    + colors.len() * size_of::<Color>()
+                    + red_bytes
+                    + blue_bytes,
+            )?;
             solutions.push((key, value));
         }
     }
 
+    monitor.merged(
+        log_prefix.len() / 2,
+        red_solutions.len() * blue_solutions.len(),
+        solutions.len(),
+    );
+
     let mut grouped = HashMap::<Vec<Vec<Value>>, QuasiSolution>::new();
     for (key, solution) in solutions {
-        grouped
-            .entry(key)
-            .and_modify(|existing_solution| {
-                *existing_solution =
-                    QuasiSolution::Plus(vec![solution.clone(), existing_solution.clone()]);
-            })
-            .or_insert(solution);
+        // The key was already accounted for when the solution was created.
+        budget.free(approximate_key_bytes(&key));
+        insert_grouped(&mut grouped, key, solution, budget)?;
     }
+    // The maps of the parts are dropped.
+    for key in red_solutions.keys().chain(blue_solutions.keys()) {
+        budget.free(approximate_key_bytes(key));
+    }
+    budget.free(red_bytes.into_iter().chain(blue_bytes).sum());
 
     log!(
         "{}Done. Found {} solutions.",
@@ -627,5 +758,35 @@ fn solve_rec(
             .map(|(_, solution)| solution.size())
             .sum::<usize>()
     );
-    grouped
+    Ok(grouped)
+}
+
+fn insert_grouped(
+    grouped: &mut HashMap<Vec<Vec<Value>>, QuasiSolution>,
+    key: Vec<Vec<Value>>,
+    solution: QuasiSolution,
+    budget: &MemoryBudget,
+) -> Result<(), Error> {
+    match grouped.get_mut(&key) {
+        Some(existing_solution) => {
+            // Both solutions move into the new node.
+            budget.allocate(size_of::<QuasiSolution>())?;
+            *existing_solution = QuasiSolution::Plus(vec![solution, existing_solution.clone()]);
+        }
+        None => {
+            budget.allocate(approximate_key_bytes(&key))?;
+            grouped.insert(key, solution);
+        }
+    }
+    Ok(())
+}
+
+// The size of a key in a map of grouped solutions.
+fn approximate_key_bytes(key: &[Vec<Value>]) -> usize {
+    size_of::<Vec<Vec<Value>>>()
+        + size_of_val(key)
+        + key
+            .iter()
+            .map(|values| size_of_val(values.as_slice()))
+            .sum::<usize>()
 }
@@ -1,3 +1,4 @@
+use super::{Error, Monitor};
 use crate::{
     game::{Constraint, Input, Output, Solution, Value},
     log,
@@ -34,7 +35,7 @@ impl ConstraintExt for Constraint {
     }
 }
 
-pub fn solve(input: &Input) -> Output {
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
     init_sum_table();
 
     let mut attempt = vec![None; input.num_cells];
@@ -45,8 +46,14 @@ pub fn solve(input: &Input) -> Output {
             affected_constraints.entry(*cell).or_insert(vec![]).push(i);
         }
     }
-    solve_rec(input, &affected_constraints, &mut attempt, &mut solutions);
-    solutions
+    solve_rec(
+        input,
+        &affected_constraints,
+        &mut attempt,
+        &mut solutions,
+        monitor,
+    )?;
+    Ok(solutions)
 }
 
 fn solve_rec(
@@ -54,25 +61,31 @@ fn solve_rec(
     affected_constraints: &HashMap<usize, Vec<usize>>,
     attempt: &mut Game,
     solutions: &mut Vec<Solution>,
-) {
-    log!("Evaluating attempt {}", format_game(attempt));
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    monitor.visit()?;
+    log!(Trace; "Evaluating attempt {}", format_game(attempt));
 
     let first_empty_cell_index = attempt.iter().position(|it| it.is_none());
     if let Some(index) = first_empty_cell_index {
         'candidates: for i in 1..=9 {
+            monitor.assign(index, index, i);
             attempt[index] = Some(i);
             for constraint_index in &affected_constraints[&index] {
                 let constraint = &input.constraints[*constraint_index];
                 if !constraint.is_satisfied_by(attempt) {
+                    monitor.fail(Some(*constraint_index));
                     continue 'candidates;
                 }
             }
-            solve_rec(input, affected_constraints, attempt, solutions);
+            solve_rec(input, affected_constraints, attempt, solutions, monitor)?;
         }
         attempt[index] = None;
     } else {
         solutions.push(attempt.iter().map(|cell| cell.unwrap()).collect());
+        monitor.found_solution();
     }
+    Ok(())
 }
 
 // A lookup table where you can look up the total number of digits as well as
@@ -0,0 +1,503 @@
+use super::{Error, Monitor};
+use crate::{
+    game::{Input, Output, Solution, Value},
+    log,
+};
+use itertools::Itertools;
+use num_bigint::BigUint;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+// The state of a run is the set of digits that are already used in it, with
+// the bit `1 << digit` for each digit like in `uniqueness`. The number of
+// filled cells and the partial sum follow from that.
+type Mask = u16;
+type State = Vec<Mask>;
+type Table = FxHashMap<State, BigUint>;
+
+pub fn solve(input: &Input, monitor: &Monitor) -> Result<Output, Error> {
+    let dp = Dp::new(input, monitor)?;
+    dp.expand(input.num_cells)
+}
+
+/// Counts the solutions without constructing them.
+pub fn count(input: &Input, monitor: &Monitor) -> Result<BigUint, Error> {
+    let dp = Dp::new(input, monitor)?;
+    let mut count = BigUint::from(9u8).pow(dp.decomposition.free_cells.len() as u32);
+    for root in &dp.decomposition.roots {
+        count *= dp.finals[*root].get(&vec![]).cloned().unwrap_or_default();
+    }
+    Ok(count)
+}
+
+/// A tree decomposition of the graph that has a node for each run and an edge
+/// between runs that share a cell. Runs without a sum are nodes as well.
+/// It's built from an elimination order: When a run is eliminated, its bag
+/// consists of the run itself and all neighbors that are not eliminated yet,
+/// which then get connected to each other.
+struct Decomposition {
+    // The elimination order. Children are always eliminated before their
+    // parents.
+    order: Vec<usize>,
+    // For each run, its bag. The first entry is always the run itself.
+    bags: Vec<Vec<usize>>,
+    children: Vec<Vec<usize>>,
+    roots: Vec<usize>,
+    // For each run, the cells that are filled in at its node. A cell is filled
+    // in at the node of its run that is eliminated first.
+    cells: Vec<Vec<usize>>,
+    // Cells that are not part of any run and can contain any digit.
+    free_cells: Vec<usize>,
+}
+
+impl Decomposition {
+    fn new(num_cells: usize, runs: &[(&[usize], Option<Value>)]) -> Self {
+        let num_runs = runs.len();
+        let mut runs_of_cell = vec![vec![]; num_cells];
+        for (i, (cells, _)) in runs.iter().enumerate() {
+            for cell in *cells {
+                runs_of_cell[*cell].push(i);
+            }
+        }
+
+        let mut neighbors = vec![FxHashSet::default(); num_runs];
+        for runs in &runs_of_cell {
+            for (a, b) in runs.iter().tuple_combinations() {
+                if a != b {
+                    neighbors[*a].insert(*b);
+                    neighbors[*b].insert(*a);
+                }
+            }
+        }
+
+        // Eliminate runs using the min-fill heuristic: Always eliminate the run
+        // whose elimination adds the fewest edges, preferring runs with fewer
+        // neighbors on ties.
+        let mut order = vec![];
+        let mut bags = vec![vec![]; num_runs];
+        let mut is_eliminated = vec![false; num_runs];
+        for _ in 0..num_runs {
+            let run = (0..num_runs)
+                .filter(|run| !is_eliminated[*run])
+                .min_by_key(|run| {
+                    let fill = neighbors[*run]
+                        .iter()
+                        .tuple_combinations()
+                        .filter(|(a, b)| !neighbors[**a].contains(*b))
+                        .count();
+                    (fill, neighbors[*run].len())
+                })
+                .unwrap();
+            let run_neighbors = neighbors[run].iter().copied().sorted().collect_vec();
+            for (a, b) in run_neighbors.iter().tuple_combinations() {
+                neighbors[*a].insert(*b);
+                neighbors[*b].insert(*a);
+            }
+            for neighbor in &run_neighbors {
+                neighbors[*neighbor].remove(&run);
+            }
+            bags[run] = std::iter::once(run).chain(run_neighbors).collect();
+            is_eliminated[run] = true;
+            order.push(run);
+        }
+
+        let mut position = vec![0; num_runs];
+        for (i, run) in order.iter().enumerate() {
+            position[*run] = i;
+        }
+        let mut children = vec![vec![]; num_runs];
+        let mut roots = vec![];
+        for run in &order {
+            match bags[*run][1..].iter().min_by_key(|it| position[**it]) {
+                Some(parent) => children[*parent].push(*run),
+                None => roots.push(*run),
+            }
+        }
+
+        let mut cells = vec![vec![]; num_runs];
+        let mut free_cells = vec![];
+        for (cell, runs) in runs_of_cell.iter().enumerate() {
+            match runs.iter().min_by_key(|run| position[**run]) {
+                Some(run) => cells[*run].push(cell),
+                None => free_cells.push(cell),
+            }
+        }
+
+        Self {
+            order,
+            bags,
+            children,
+            roots,
+            cells,
+            free_cells,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.bags.iter().map(|bag| bag.len()).max().unwrap_or(1) - 1
+    }
+}
+
+/// A step in computing the table of a node. The positions refer to the runs of
+/// the node's bag.
+enum Step {
+    Join { child: usize, positions: Vec<usize> },
+    Fill { cell: usize, positions: Vec<usize> },
+}
+
+struct Dp<'a> {
+    // The runs of the input with their sums, as returned by `Input::runs`.
+    runs: Vec<(&'a [usize], Option<Value>)>,
+    monitor: &'a Monitor,
+    decomposition: Decomposition,
+    steps: Vec<Vec<Step>>,
+    // For each node, the tables before and after each step. Keys contain one
+    // state for each run in the bag.
+    tables: Vec<Vec<Table>>,
+    // For each node, the final table. Keys contain one state for each run in
+    // the bag except the node's own run, which is complete at this point.
+    finals: Vec<Table>,
+}
+
+impl<'a> Dp<'a> {
+    fn new(input: &'a Input, monitor: &'a Monitor) -> Result<Self, Error> {
+        let runs = input.runs();
+        let decomposition = Decomposition::new(input.num_cells, &runs);
+        log!(
+            Info, runs = runs.len(), width = decomposition.width();
+            "Decomposed the runs into a tree."
+        );
+
+        let num_runs = runs.len();
+        let mut dp = Self {
+            runs,
+            monitor,
+            steps: (0..num_runs).map(|_| vec![]).collect(),
+            tables: vec![vec![]; num_runs],
+            finals: vec![Table::default(); num_runs],
+            decomposition,
+        };
+        for (i, run) in dp.decomposition.order.clone().into_iter().enumerate() {
+            monitor.choose(0, i, num_runs);
+            dp.compute_node(run)?;
+        }
+        Ok(dp)
+    }
+
+    fn compute_node(&mut self, run: usize) -> Result<(), Error> {
+        let bag = &self.decomposition.bags[run];
+        let position_in_bag = |other: &usize| bag.iter().position(|it| it == other).unwrap();
+
+        let mut steps = vec![];
+        for child in &self.decomposition.children[run] {
+            steps.push(Step::Join {
+                child: *child,
+                positions: self.decomposition.bags[*child][1..]
+                    .iter()
+                    .map(position_in_bag)
+                    .collect(),
+            });
+        }
+        for cell in &self.decomposition.cells[run] {
+            steps.push(Step::Fill {
+                cell: *cell,
+                positions: self
+                    .runs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (cells, _))| cells.contains(cell))
+                    .map(|(i, _)| position_in_bag(&i))
+                    .collect(),
+            });
+        }
+
+        let mut table = Table::default();
+        table.insert(vec![0; bag.len()], BigUint::from(1u8));
+        let mut tables = vec![table];
+        for step in &steps {
+            let previous = tables.last().unwrap();
+            let mut table = Table::default();
+            match step {
+                Step::Join { child, positions } => {
+                    for (state, count) in previous {
+                        self.monitor.visit()?;
+                        'child_states: for (child_state, child_count) in &self.finals[*child] {
+                            let mut state = state.clone();
+                            for (position, mask) in positions.iter().zip(child_state) {
+                                if state[*position] & mask != 0 {
+                                    continue 'child_states; // A digit appears twice.
+                                }
+                                state[*position] |= mask;
+                                if !self.is_completable(bag[*position], state[*position]) {
+                                    continue 'child_states;
+                                }
+                            }
+                            *table.entry(state).or_default() += count * child_count;
+                        }
+                    }
+                }
+                Step::Fill { positions, .. } => {
+                    for (state, count) in previous {
+                        self.monitor.visit()?;
+                        'digits: for digit in 1..=9 {
+                            let bit = 1 << digit;
+                            let mut state = state.clone();
+                            for position in positions {
+                                if state[*position] & bit != 0 {
+                                    continue 'digits; // A digit appears twice.
+                                }
+                                state[*position] |= bit;
+                                if !self.is_completable(bag[*position], state[*position]) {
+                                    continue 'digits;
+                                }
+                            }
+                            *table.entry(state).or_default() += count;
+                        }
+                    }
+                }
+            }
+            tables.push(table);
+        }
+
+        let mut table = Table::default();
+        for (state, count) in tables.last().unwrap() {
+            if self.is_complete(run, state[0]) {
+                *table.entry(state[1..].to_vec()).or_default() += count;
+            }
+        }
+        log!(
+            run = run, bag = bag.len(), states = table.len();
+            "Computed the table of a node."
+        );
+
+        self.steps[run] = steps;
+        self.tables[run] = tables;
+        self.finals[run] = table;
+        Ok(())
+    }
+
+    fn is_completable(&self, run: usize, mask: Mask) -> bool {
+        let (cells, sum) = self.runs[run];
+        let num_missing = cells.len() as isize - mask.count_ones() as isize;
+        if !(0..=9).contains(&num_missing) {
+            return false;
+        }
+        let reachable = REACHABLE_SUMS[mask as usize][num_missing as usize];
+        match sum {
+            Some(sum) => sum <= 45 && reachable & (1 << sum) != 0,
+            // Without a sum, the missing digits just have to exist.
+            None => reachable != 0,
+        }
+    }
+
+    fn is_complete(&self, run: usize, mask: Mask) -> bool {
+        let (cells, sum) = self.runs[run];
+        mask.count_ones() as usize == cells.len() && sum.is_none_or(|sum| digit_sum(mask) == sum)
+    }
+
+    // Constructs solutions by walking the tables backwards. This is a depth
+    // first search with an explicit stack, because the search is as deep as
+    // there are cells and runs, which overflows the call stack on big inputs.
+    fn expand(&self, num_cells: usize) -> Result<Output, Error> {
+        let mut expansion = Expansion {
+            pending: self
+                .decomposition
+                .roots
+                .iter()
+                .map(|root| (*root, &[][..]))
+                .collect(),
+            attempt: vec![None; num_cells],
+            solutions: vec![],
+        };
+        let mut stack = vec![self.frame(Goal::Node, &mut expansion)];
+        while let Some(frame) = stack.last_mut() {
+            self.monitor.visit()?;
+            match frame.applied.take() {
+                Some(Change::Pending(..)) => {
+                    expansion.pending.pop();
+                }
+                Some(Change::Cell(cell, _)) => expansion.attempt[cell] = None,
+                None => {}
+            }
+            match frame.choices.next() {
+                Some((change, goal)) => {
+                    match change {
+                        Some(Change::Pending(run, state)) => expansion.pending.push((run, state)),
+                        Some(Change::Cell(cell, digit)) => expansion.attempt[cell] = Some(digit),
+                        None => {}
+                    }
+                    frame.applied = change;
+                    let frame = self.frame(goal, &mut expansion);
+                    stack.push(frame);
+                }
+                None => {
+                    if let Some(taken) = stack.pop().unwrap().taken {
+                        expansion.pending.push(taken);
+                    }
+                }
+            }
+        }
+        Ok(expansion.solutions)
+    }
+
+    // The ways to continue the expansion of the goal. Solutions are recorded
+    // when there's nothing left to expand.
+    fn frame(&'a self, goal: Goal, expansion: &mut Expansion<'a>) -> Frame<'a> {
+        let mut choices = vec![];
+        let mut taken = None;
+        match goal {
+            Goal::Node => match expansion.pending.pop() {
+                Some((run, state)) => {
+                    for full_state in self.tables[run].last().unwrap().keys() {
+                        if full_state[1..] == *state && self.is_complete(run, full_state[0]) {
+                            let num_steps = self.steps[run].len();
+                            choices.push((None, Goal::step(run, num_steps, full_state.clone())));
+                        }
+                    }
+                    taken = Some((run, state));
+                }
+                None => choices.push((None, Goal::FreeCell(0))),
+            },
+            Goal::Step {
+                run,
+                num_steps,
+                state,
+            } => {
+                let previous = &self.tables[run][num_steps - 1];
+                match &self.steps[run][num_steps - 1] {
+                    Step::Join { child, positions } => {
+                        'child_states: for child_state in self.finals[*child].keys() {
+                            let mut state = state.clone();
+                            for (position, mask) in positions.iter().zip(child_state) {
+                                if state[*position] & mask != *mask {
+                                    continue 'child_states;
+                                }
+                                state[*position] &= !mask;
+                            }
+                            if previous.contains_key(&state) {
+                                choices.push((
+                                    Some(Change::Pending(*child, child_state)),
+                                    Goal::step(run, num_steps - 1, state),
+                                ));
+                            }
+                        }
+                    }
+                    Step::Fill { cell, positions } => {
+                        'digits: for digit in 1..=9 {
+                            let bit = 1 << digit;
+                            let mut state = state.clone();
+                            for position in positions {
+                                if state[*position] & bit == 0 {
+                                    continue 'digits;
+                                }
+                                state[*position] &= !bit;
+                            }
+                            if previous.contains_key(&state) {
+                                choices.push((
+                                    Some(Change::Cell(*cell, digit)),
+                                    Goal::step(run, num_steps - 1, state),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            Goal::FreeCell(index) => match self.decomposition.free_cells.get(index) {
+                Some(cell) => {
+                    for digit in 1..=9 {
+                        choices.push((Some(Change::Cell(*cell, digit)), Goal::FreeCell(index + 1)));
+                    }
+                }
+                None => {
+                    let solution = expansion.attempt.iter().map(|cell| cell.unwrap());
+                    expansion.solutions.push(solution.collect());
+                    self.monitor.found_solution();
+                }
+            },
+        }
+        Frame {
+            choices: choices.into_iter(),
+            applied: None,
+            taken,
+        }
+    }
+}
+
+/// The partial solution while walking the tables backwards.
+struct Expansion<'a> {
+    // The nodes that still need to be expanded, with the state their final
+    // table has to have.
+    pending: Vec<(usize, &'a [Mask])>,
+    attempt: Vec<Option<Value>>,
+    solutions: Vec<Solution>,
+}
+
+/// What still needs to be expanded next.
+enum Goal {
+    // The next node on the pending list.
+    Node,
+    // The steps of a node before the given number of steps, with the state of
+    // the table after them. Nodes whose steps are all expanded continue with
+    // the next pending node.
+    Step {
+        run: usize,
+        num_steps: usize,
+        state: State,
+    },
+    // The free cells starting at the index.
+    FreeCell(usize),
+}
+
+impl Goal {
+    fn step(run: usize, num_steps: usize, state: State) -> Self {
+        if num_steps == 0 {
+            Goal::Node
+        } else {
+            Goal::Step {
+                run,
+                num_steps,
+                state,
+            }
+        }
+    }
+}
+
+/// A change to the expansion that is undone when backtracking.
+#[derive(Clone, Copy)]
+enum Change<'a> {
+    Pending(usize, &'a [Mask]),
+    Cell(usize, Value),
+}
+
+struct Frame<'a> {
+    choices: std::vec::IntoIter<(Option<Change<'a>>, Goal)>,
+    // The change of the choice that is currently expanded.
+    applied: Option<Change<'a>>,
+    // The pending node this frame expands, which is pending again once all
+    // choices are explored.
+    taken: Option<(usize, &'a [Mask])>,
+}
+
+fn digit_sum(mask: Mask) -> Value {
+    (1..=9).filter(|digit| mask & (1 << digit) != 0).sum()
+}
+
+lazy_static! {
+    // first, outer array: which digits have already been used
+    // second, inner array: how many digits still need to be filled in
+    // value: bitmask of the total sums that can be reached
+    static ref REACHABLE_SUMS: [[u64; 10]; 1 << 10] = calculate_reachable_sums();
+}
+
+fn calculate_reachable_sums() -> [[u64; 10]; 1 << 10] {
+    let mut table = [[0u64; 10]; 1 << 10];
+    // Bit 0 doesn't stand for a digit, so only even masks occur.
+    for used in (0..(1 << 10) as Mask).step_by(2) {
+        for additional in (0..(1 << 10) as Mask).step_by(2) {
+            if used & additional == 0 {
+                table[used as usize][additional.count_ones() as usize] |=
+                    1 << digit_sum(used | additional);
+            }
+        }
+    }
+    table
+}
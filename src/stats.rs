@@ -0,0 +1,120 @@
+//! Statistics about the runtimes measured by benchmarks. Runtimes are skewed:
+//! Most runs take about the same time, but some get interrupted by the
+//! operating system and take much longer. That's why we prefer statistics
+//! based on the median over ones based on the mean.
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+pub fn sorted(values: &[f64]) -> Vec<f64> {
+    values.iter().copied().sorted_by(f64::total_cmp).collect()
+}
+
+/// The median of values that are already sorted.
+pub fn median(sorted: &[f64]) -> f64 {
+    let count = sorted.len();
+    if count.is_multiple_of(2) {
+        (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0
+    } else {
+        sorted[count / 2]
+    }
+}
+
+/// The value below which the given fraction of the sorted values lie, linearly
+/// interpolating between neighboring values.
+pub fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let position = (sorted.len() - 1) as f64 * fraction;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+/// The median absolute deviation from the median. Unlike the standard
+/// deviation, a few very slow runs barely change it.
+pub fn median_absolute_deviation(sorted: &[f64]) -> f64 {
+    let median = median(sorted);
+    let deviations = sorted
+        .iter()
+        .map(|value| (value - median).abs())
+        .collect_vec();
+    self::median(&self::sorted(&deviations))
+}
+
+/// The indices of values that are far away from the median, measured in
+/// median absolute deviations. This is the modified z-score by Iglewicz and
+/// Hoaglin, which calls values with a score above 3.5 outliers.
+pub fn outliers(values: &[f64]) -> Vec<usize> {
+    let sorted = sorted(values);
+    let median = median(&sorted);
+    let deviation = median_absolute_deviation(&sorted);
+    if deviation == 0.0 {
+        return vec![];
+    }
+    values
+        .iter()
+        .positions(|value| 0.6745 * (value - median).abs() / deviation > 3.5)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Interval {
+    pub low: f64,
+    pub high: f64,
+}
+impl Interval {
+    pub fn contains(&self, value: f64) -> bool {
+        self.low <= value && value <= self.high
+    }
+}
+
+const BOOTSTRAP_RESAMPLES: usize = 10000;
+
+/// Computes a statistic for many resamples of the values, each drawn with
+/// replacement, and returns the interval containing the middle `confidence`
+/// fraction of the results. The randomness is seeded, so the same values
+/// always result in the same interval.
+fn bootstrap(confidence: f64, mut statistic: impl FnMut(&mut StdRng) -> f64) -> Interval {
+    let mut rng = StdRng::seed_from_u64(0);
+    let results = sorted(
+        &(0..BOOTSTRAP_RESAMPLES)
+            .map(|_| statistic(&mut rng))
+            .collect_vec(),
+    );
+    let tail = (1.0 - confidence) / 2.0;
+    let index = |fraction: f64| ((results.len() - 1) as f64 * fraction).round() as usize;
+    Interval {
+        low: results[index(tail)],
+        high: results[index(1.0 - tail)],
+    }
+}
+
+fn resampled_median(values: &[f64], rng: &mut StdRng) -> f64 {
+    let resampled = (0..values.len())
+        .map(|_| values[rng.gen_range(0..values.len())])
+        .collect_vec();
+    median(&sorted(&resampled))
+}
+
+/// A bootstrap confidence interval for the median.
+pub fn bootstrap_median(values: &[f64], confidence: f64) -> Interval {
+    bootstrap(confidence, |rng| resampled_median(values, rng))
+}
+
+/// A bootstrap confidence interval for the ratio of the medians of `b` and
+/// `a`. Both samples are resampled independently.
+pub fn bootstrap_median_ratio(a: &[f64], b: &[f64], confidence: f64) -> Interval {
+    bootstrap(confidence, |rng| {
+        let a = resampled_median(a, rng);
+        let b = resampled_median(b, rng);
+        b / a
+    })
+}
+
+/// Whether the confidence interval of the median is narrower than the given
+/// fraction of the median in both directions.
+pub fn is_median_precise(values: &[f64], confidence: f64, precision: f64) -> bool {
+    let median = median(&sorted(values));
+    let interval = bootstrap_median(values, confidence);
+    interval.low >= median * (1.0 - precision) && interval.high <= median * (1.0 + precision)
+}
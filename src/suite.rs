@@ -0,0 +1,191 @@
+//! Benchmark suites are JSON manifests listing puzzles. Each puzzle can have
+//! tags, the number of solutions it has or a file containing its reference
+//! solutions (see the `reference` module), and a timeout:
+//!
+//! ```json
+//! {
+//!   "description": "Puzzles with a unique solution.",
+//!   "puzzles": [
+//!     { "file": "../kakuros/small.kakuro", "tags": ["tiny"], "solutions": 6 },
+//!     {
+//!       "file": "../kakuros/20x20.kakuro",
+//!       "solution_file": "../kakuros/20x20-solution.json",
+//!       "timeout": "5m"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Paths are relative to the manifest. Suites in the `suites` directory of
+//! this repository can be referred to by their name, like `quick`.
+
+use crate::{
+    board::ParseBoard,
+    game::{Input, Output, Solution},
+    parse_duration, reference,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const SUITES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/suites");
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    description: String,
+    puzzles: Vec<ManifestPuzzle>,
+}
+
+#[derive(Deserialize)]
+struct ManifestPuzzle {
+    file: PathBuf,
+    #[serde(default)]
+    tags: Vec<String>,
+    solutions: Option<u64>,
+    solution_file: Option<PathBuf>,
+    timeout: Option<String>,
+}
+
+pub struct Suite {
+    pub name: String,
+    pub description: String,
+    /// Where the manifest is.
+    pub path: PathBuf,
+    pub puzzles: Vec<Puzzle>,
+}
+
+pub struct Puzzle {
+    pub file: PathBuf,
+    pub tags: Vec<String>,
+    /// How many solutions the puzzle has.
+    pub solutions: Option<u64>,
+    /// All solutions of the puzzle.
+    pub reference: Option<BTreeSet<Solution>>,
+    pub timeout: Option<Duration>,
+}
+
+impl Suite {
+    /// Loads the suite with the given name from the `suites` directory, or
+    /// from the given path if it's a file.
+    pub fn find(name_or_path: &str) -> Result<Self, String> {
+        let path = Path::new(name_or_path);
+        if path.is_file() {
+            Self::load(path)
+        } else {
+            Self::load(&Path::new(SUITES_DIR).join(format!("{}.json", name_or_path)))
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read the suite {:?}: {}", path, err))?;
+        let manifest: Manifest = serde_json::from_str(&json)
+            .map_err(|err| format!("The suite {:?} is invalid: {}", path, err))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let resolve = |file: &Path| {
+            let file = dir.join(file);
+            fs::canonicalize(&file).map_err(|err| format!("Couldn't find {:?}: {}", file, err))
+        };
+        let puzzles = manifest
+            .puzzles
+            .into_iter()
+            .map(|puzzle| {
+                let file = resolve(&puzzle.file)?;
+                let reference = match &puzzle.solution_file {
+                    Some(solution_file) => {
+                        let board = fs::read_to_string(&file)
+                            .map_err(|err| format!("Couldn't read {:?}: {}", file, err))?
+                            .parse_board()?;
+                        Some(reference::read(&resolve(solution_file)?, &board)?)
+                    }
+                    None => None,
+                };
+                Ok(Puzzle {
+                    file,
+                    tags: puzzle.tags,
+                    solutions: puzzle.solutions,
+                    reference,
+                    timeout: puzzle.timeout.as_deref().map(parse_duration).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self {
+            name: path
+                .file_stem()
+                .map_or(String::new(), |it| it.to_string_lossy().to_string()),
+            description: manifest.description,
+            path: path.to_path_buf(),
+            puzzles,
+        })
+    }
+
+    /// Only keeps the puzzles with the given tag.
+    pub fn with_tag(self, tag: &str) -> Self {
+        Self {
+            puzzles: self
+                .puzzles
+                .into_iter()
+                .filter(|puzzle| puzzle.tags.iter().any(|it| it == tag))
+                .collect(),
+            ..self
+        }
+    }
+
+    /// The puzzle of the given file, if the suite contains it.
+    pub fn puzzle(&self, file: &Path) -> Option<&Puzzle> {
+        let file = fs::canonicalize(file).ok()?;
+        self.puzzles.iter().find(|puzzle| puzzle.file == file)
+    }
+}
+
+impl Puzzle {
+    /// Checks that the solutions found by a solver are valid and match what
+    /// the manifest expects.
+    pub fn check(&self, input: &Input, output: &Output) -> Result<(), String> {
+        if let Some(invalid) = output.iter().find(|it| !input.is_solution(it)) {
+            return Err(format!("Found an invalid solution {:?}.", invalid));
+        }
+        if let Some(solutions) = self.solutions {
+            if output.len() as u64 != solutions {
+                return Err(format!(
+                    "Found {} solutions, expected {}.",
+                    output.len(),
+                    solutions
+                ));
+            }
+        }
+        match &self.reference {
+            Some(reference) => reference::verify(input, output, reference),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solvers::{self, Monitor};
+
+    #[test]
+    fn golden_references_are_all_solutions() {
+        let suite = Suite::find("golden").unwrap();
+        for puzzle in &suite.puzzles {
+            let reference = puzzle.reference.as_ref().unwrap();
+            let input = fs::read_to_string(&puzzle.file)
+                .unwrap()
+                .parse_board()
+                .unwrap()
+                .to_input();
+            assert!(reference.iter().all(|it| input.is_solution(it)));
+            let output = solvers::no_alloc::solve(&input, &Monitor::unlimited()).unwrap();
+            if let Err(message) = puzzle.check(&input, &output) {
+                panic!("{:?}: {}", puzzle.file, message);
+            }
+        }
+    }
+}
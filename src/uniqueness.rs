@@ -0,0 +1,351 @@
+//! Finding out whether a Kakuro has a unique solution. The solvers find all
+//! solutions, which takes forever for Kakuros with astronomically many of them.
+//! This search stops as soon as it found a given number of solutions. It
+//! always continues with the cell that has the fewest possible digits left.
+
+use crate::{
+    game::{Input, Solution, Value},
+    generate::{self, Cell},
+    layout::{self, Layout},
+    solvers::{Error, Monitor},
+};
+use itertools::Itertools;
+use rand::{seq::SliceRandom, Rng};
+
+/// The bits 1 to 9, one for each digit.
+pub const ALL_DIGITS: u16 = 0b11_1111_1110;
+
+/// Finds up to `limit` solutions of the input.
+pub fn solutions(input: &Input, limit: usize, monitor: &Monitor) -> Result<Vec<Solution>, Error> {
+    let mut search = Search::new(input);
+    let mut solutions = vec![];
+    search.solve(limit, &mut solutions, monitor)?;
+    Ok(solutions)
+}
+
+struct Search {
+    /// For each cell, the runs it's part of.
+    constraints_of_cell: Vec<Vec<usize>>,
+    /// For each cell, its digit or 0.
+    digits: Vec<Value>,
+    /// For each run, the digits used by its cells.
+    used: Vec<u16>,
+    /// For each run with a sum, the sum that's still missing.
+    missing_sums: Vec<Option<i32>>,
+    /// For each run, how many of its cells have no digit yet.
+    empty_cells: Vec<u32>,
+}
+
+impl Search {
+    fn new(input: &Input) -> Self {
+        let runs = input.runs();
+        let mut constraints_of_cell = vec![vec![]; input.num_cells];
+        for (i, (cells, _)) in runs.iter().enumerate() {
+            for cell in *cells {
+                constraints_of_cell[*cell].push(i);
+            }
+        }
+        Self {
+            constraints_of_cell,
+            digits: vec![0; input.num_cells],
+            used: vec![0; runs.len()],
+            missing_sums: runs
+                .iter()
+                .map(|(_, sum)| sum.map(|sum| sum as i32))
+                .collect(),
+            empty_cells: runs.iter().map(|(cells, _)| cells.len() as u32).collect(),
+        }
+    }
+
+    /// The digits that the cell can still get.
+    fn candidates(&self, cell: usize) -> u16 {
+        let constraints = &self.constraints_of_cell[cell];
+        let mut candidates = constraints
+            .iter()
+            .fold(ALL_DIGITS, |candidates, c| candidates & !self.used[*c]);
+        for digit in 1..=9 {
+            let bit = 1 << digit;
+            if candidates & bit == 0 {
+                continue;
+            }
+            let is_possible = constraints.iter().all(|c| {
+                self.missing_sums[*c].is_none_or(|missing| {
+                    is_sum_reachable(
+                        ALL_DIGITS & !self.used[*c] & !bit,
+                        self.empty_cells[*c] - 1,
+                        missing - digit,
+                    )
+                })
+            });
+            if !is_possible {
+                candidates &= !bit;
+            }
+        }
+        candidates
+    }
+
+    fn set(&mut self, cell: usize, digit: Value) {
+        self.digits[cell] = digit;
+        for c in &self.constraints_of_cell[cell] {
+            self.used[*c] |= 1 << digit;
+            if let Some(missing) = &mut self.missing_sums[*c] {
+                *missing -= digit as i32;
+            }
+            self.empty_cells[*c] -= 1;
+        }
+    }
+
+    fn unset(&mut self, cell: usize) {
+        let digit = self.digits[cell];
+        self.digits[cell] = 0;
+        for c in &self.constraints_of_cell[cell] {
+            self.used[*c] &= !(1 << digit);
+            if let Some(missing) = &mut self.missing_sums[*c] {
+                *missing += digit as i32;
+            }
+            self.empty_cells[*c] += 1;
+        }
+    }
+
+    fn solve(
+        &mut self,
+        limit: usize,
+        solutions: &mut Vec<Solution>,
+        monitor: &Monitor,
+    ) -> Result<(), Error> {
+        monitor.visit()?;
+        let mut best: Option<(usize, u16)> = None;
+        for cell in 0..self.digits.len() {
+            if self.digits[cell] != 0 {
+                continue;
+            }
+            let candidates = self.candidates(cell);
+            if best.is_none_or(|(_, best)| candidates.count_ones() < best.count_ones()) {
+                best = Some((cell, candidates));
+                if candidates.count_ones() <= 1 {
+                    break;
+                }
+            }
+        }
+        let (cell, candidates) = match best {
+            Some(best) => best,
+            None => {
+                monitor.found_solution();
+                solutions.push(self.digits.clone());
+                return Ok(());
+            }
+        };
+        for digit in 1..=9 {
+            if candidates & (1 << digit) == 0 {
+                continue;
+            }
+            self.set(cell, digit);
+            let result = self.solve(limit, solutions, monitor);
+            self.unset(cell);
+            result?;
+            if solutions.len() >= limit {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `count` different digits out of the available ones can add up to
+/// the sum.
+fn is_sum_reachable(available: u16, count: u32, sum: i32) -> bool {
+    if available.count_ones() < count {
+        return false;
+    }
+    let digits = (1..=9).filter(|digit| available & (1 << digit) != 0);
+    let min: i32 = digits.clone().take(count as usize).sum();
+    let max: i32 = digits.rev().take(count as usize).sum();
+    min <= sum && sum <= max
+}
+
+/// After this many tried changes, a fill that's still not unique is replaced
+/// by a new one.
+const MAX_CHANGES: usize = 2000;
+/// After this many fills, the layout is given up. Some layouts rarely or never
+/// get a unique solution, so callers are better off trying another one.
+const MAX_FILLS: usize = 100;
+
+/// Fills the layout with digits so that the resulting Kakuro has a unique
+/// solution. Starts with a random fill and repeatedly changes the digit of a
+/// random cell that's still open after propagating the constraints. Changes
+/// are kept if they don't leave more digits open. Whether the solution is
+/// unique even though digits are still open takes a search, so that's only
+/// checked when fewer digits are open than ever before and at most one per
+/// cell. Fails if the monitor stops the search, the layout can't be filled or
+/// none of `MAX_FILLS` fills became unique.
+pub fn fill_uniquely(
+    layout: &Layout,
+    rand: &mut impl Rng,
+    monitor: &Monitor,
+) -> Result<Vec<Vec<Cell>>, String> {
+    let cells = layout_cells(layout);
+    for _ in 0..MAX_FILLS {
+        let mut grid = layout::fill(layout, rand)?;
+        let mut candidates = propagate(&generate::to_board(&grid).to_input());
+        let mut fewest_open = usize::MAX;
+        for _ in 0..MAX_CHANGES {
+            monitor.check().map_err(|err| err.to_string())?;
+            let open = open_digits(&candidates);
+            if open == 0 {
+                return Ok(grid);
+            }
+            if open < fewest_open {
+                fewest_open = open;
+                if open <= cells.len()
+                    && solutions(&generate::to_board(&grid).to_input(), 2, monitor)
+                        .map_err(|err| err.to_string())?
+                        .len()
+                        == 1
+                {
+                    return Ok(grid);
+                }
+            }
+            let open_cells = cells
+                .iter()
+                .zip(&candidates)
+                .filter(|(_, candidates)| candidates.count_ones() > 1)
+                .map(|(position, _)| *position)
+                .collect_vec();
+            let (x, y) = *open_cells.choose(rand).unwrap();
+            let used = digits_in_runs(&grid, x, y);
+            let current = grid[y][x];
+            let other_digits = (1..=9)
+                .filter(|digit| !matches!(current, Cell::Value(it) if it == *digit))
+                .filter(|digit| !used.contains(digit))
+                .collect_vec();
+            let digit = match other_digits.choose(rand) {
+                Some(digit) => *digit,
+                None => continue,
+            };
+            grid[y][x] = Cell::Value(digit);
+            let new_candidates = propagate(&generate::to_board(&grid).to_input());
+            if open_digits(&new_candidates) <= open_digits(&candidates) {
+                candidates = new_candidates;
+            } else {
+                grid[y][x] = current;
+            }
+        }
+    }
+    Err(format!(
+        "None of {} fills of the layout had a unique solution.",
+        MAX_FILLS
+    ))
+}
+
+/// For each cell, the digits it can have according to the constraints.
+pub fn propagate(input: &Input) -> Vec<u16> {
+    let mut candidates = vec![ALL_DIGITS; input.num_cells];
+    Propagator::new(input).propagate(&mut candidates);
+    candidates
+}
+
+/// Narrows down the digits that cells can have. Each run only allows the
+/// digits of the combinations of digits that add up to its sum and fit into
+/// its cells. Digits of cells that only have one left are removed from the
+/// other cells of their runs. This is repeated until nothing changes anymore.
+pub struct Propagator<'a> {
+    /// The runs with the combinations of digits that add up to their sums, or
+    /// all combinations that fit into them if they don't have a sum.
+    runs: Vec<(&'a [usize], Vec<u16>)>,
+}
+
+impl<'a> Propagator<'a> {
+    pub fn new(input: &'a Input) -> Self {
+        let runs = input
+            .runs()
+            .into_iter()
+            .map(|(cells, sum)| {
+                let combinations = (0..=ALL_DIGITS)
+                    .filter(|combination| combination & !ALL_DIGITS == 0)
+                    .filter(|combination| combination.count_ones() as usize == cells.len())
+                    .filter(|combination| {
+                        sum.is_none_or(|sum| {
+                            (1..=9)
+                                .filter(|digit| combination & (1 << digit) != 0)
+                                .sum::<Value>()
+                                == sum
+                        })
+                    })
+                    .collect_vec();
+                (cells, combinations)
+            })
+            .collect();
+        Self { runs }
+    }
+
+    /// Narrows down the candidates, which contain the digits each cell can
+    /// have as bits.
+    pub fn propagate(&self, candidates: &mut [u16]) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (cells, combinations) in &self.runs {
+                let possible = cells
+                    .iter()
+                    .fold(0, |possible, cell| possible | candidates[*cell]);
+                let allowed = combinations
+                    .iter()
+                    .filter(|combination| *combination & !possible == 0)
+                    .filter(|combination| {
+                        cells
+                            .iter()
+                            .all(|cell| candidates[*cell] & *combination != 0)
+                    })
+                    .fold(0, |allowed, combination| allowed | combination);
+                let fixed = cells
+                    .iter()
+                    .map(|cell| candidates[*cell])
+                    .filter(|candidates| candidates.count_ones() == 1)
+                    .fold(0, |fixed, digit| fixed | digit);
+                for cell in *cells {
+                    let mut new = candidates[*cell] & allowed;
+                    if new.count_ones() > 1 {
+                        new &= !fixed;
+                    }
+                    if new != candidates[*cell] {
+                        candidates[*cell] = new;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How many digits are still open in addition to one per cell.
+pub fn open_digits(candidates: &[u16]) -> usize {
+    candidates
+        .iter()
+        .map(|candidates| (candidates.count_ones() as usize).saturating_sub(1))
+        .sum()
+}
+
+/// The positions of the cells of the layout, in the order in which
+/// `Board::to_input` numbers them.
+fn layout_cells(layout: &Layout) -> Vec<(usize, usize)> {
+    (0..layout.len())
+        .cartesian_product(0..layout[0].len())
+        .filter(|(y, x)| layout[*y][*x])
+        .map(|(y, x)| (x, y))
+        .collect()
+}
+
+/// The digits of the other cells in the runs of the cell.
+fn digits_in_runs(grid: &[Vec<Cell>], x: usize, y: usize) -> Vec<Value> {
+    let digit = |cell: &Cell| match cell {
+        Cell::Value(digit) => Some(*digit),
+        Cell::Wall => None,
+    };
+    let row = &grid[y];
+    let column = grid.iter().map(|row| &row[x]).collect_vec();
+    let left = row[..x].iter().rev().map_while(digit);
+    let right = row[x + 1..].iter().map_while(digit);
+    let above = column[..y].iter().rev().map_while(|it| digit(it));
+    let below = column[y + 1..].iter().map_while(|it| digit(it));
+    left.chain(right).chain(above).chain(below).collect()
+}